@@ -0,0 +1,36 @@
+//! Turns the kernel ELF into bootable BIOS and UEFI disk images.
+//!
+//! Usage: `cargo run --manifest-path boot/Cargo.toml -- <path to kernel ELF>`
+//!
+//! The images are written next to the kernel, as `<kernel>-bios.img` and `<kernel>-uefi.img`.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+fn image_path(kernel: &Path, kind: &str) -> PathBuf {
+    let name = kernel.file_name().unwrap().to_string_lossy();
+    kernel.with_file_name(format!("{name}-{kind}.img"))
+}
+
+fn main() -> ExitCode {
+    let Some(kernel) = std::env::args_os().nth(1).map(PathBuf::from) else {
+        eprintln!("Usage: boot <path to kernel ELF>");
+        return ExitCode::FAILURE;
+    };
+
+    let bios = image_path(&kernel, "bios");
+    if let Err(e) = bootloader::BiosBoot::new(&kernel).create_disk_image(&bios) {
+        eprintln!("Failed to create the BIOS image: {e:?}");
+        return ExitCode::FAILURE;
+    }
+    println!("{}", bios.display());
+
+    let uefi = image_path(&kernel, "uefi");
+    if let Err(e) = bootloader::UefiBoot::new(&kernel).create_disk_image(&uefi) {
+        eprintln!("Failed to create the UEFI image: {e:?}");
+        return ExitCode::FAILURE;
+    }
+    println!("{}", uefi.display());
+
+    ExitCode::SUCCESS
+}
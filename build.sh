@@ -1,3 +1,3 @@
 #cargo +nightly build --target x86_64-blog_os.json
-#cargo build --target x86_64-blog_os.json
-cargo bootimage
+(cd kernel && cargo build) &&\
+    cargo run --manifest-path boot/Cargo.toml -- kernel/target/x86_64-blog_os/debug/python_os
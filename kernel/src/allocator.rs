@@ -0,0 +1,351 @@
+//! The kernel heap.
+//!
+//! The heap lives in its own virtual window starting at `HEAP_START`. Only `INITIAL_HEAP_SIZE`
+//! bytes are mapped at boot; whenever `linked_list_allocator` runs out of space, fresh frames
+//! are mapped at the top of the heap and handed to it. The heap can grow until the frame
+//! allocator runs dry or the window is exhausted.
+//!
+//! Small allocations are served from the size classes in `slab` instead, see there.
+//!
+//! When the heap cannot grow any further, allocations are served from a small emergency pool
+//! and `signals::SIGMEMORY` is raised, so the interpreter gets the chance to raise a
+//! `MemoryError` and free memory instead of the kernel halting.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+
+use crate::{memory, signals};
+
+pub mod bench;
+mod slab;
+pub mod tracking;
+
+use slab::Slabs;
+
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// Size of the virtual window reserved for the heap.
+pub const HEAP_MAX_SIZE: usize = 1 << 40;
+/// Bytes mapped at boot, before the first allocation.
+pub const INITIAL_HEAP_SIZE: usize = 4 * 1024 * 1024;
+/// The heap grows by at least this much at a time, to keep page table churn down.
+const MIN_GROWTH: usize = 1024 * 1024;
+
+const PAGE_SIZE: usize = 4096;
+
+pub const EMERGENCY_POOL_START: usize = 0x_4444_0000_0000;
+/// Enough for the interpreter to create and raise an exception while the heap is exhausted.
+pub const EMERGENCY_POOL_SIZE: usize = 1024 * 1024;
+
+/// Set when an allocation could not be served from the heap.
+static OUT_OF_MEMORY: AtomicBool = AtomicBool::new(false);
+
+#[global_allocator]
+pub static ALLOCATOR: GrowableHeap = GrowableHeap::empty();
+
+/// A `linked_list_allocator` heap which maps more memory when it runs out, with size-class
+/// free lists in front of it for small allocations.
+pub struct GrowableHeap {
+    heap: Mutex<Heap>,
+    slabs: Mutex<Slabs>,
+    emergency: Mutex<Heap>,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    /// Bytes requested by live allocations, not counting size class rounding.
+    live_bytes: AtomicUsize,
+}
+
+/// Allocation counters, cheap enough to read around every REPL statement.
+#[derive(Clone, Copy)]
+pub struct Counters {
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub live_bytes: usize,
+}
+
+impl Counters {
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.deallocations
+    }
+}
+
+/// A snapshot of the heap, see `GrowableHeap::stats`.
+pub struct HeapStats {
+    /// Bytes currently mapped for the heap.
+    pub size: usize,
+    /// Bytes handed out by the linked list heap, including chunks owned by size classes.
+    pub used: usize,
+    pub free: usize,
+    pub largest_free_block: usize,
+    /// Free bytes sitting in the size classes.
+    pub slab_cached: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub live_bytes: usize,
+}
+
+impl GrowableHeap {
+    pub const fn empty() -> Self {
+        Self {
+            heap: Mutex::new(Heap::empty()),
+            slabs: Mutex::new(Slabs::new()),
+            emergency: Mutex::new(Heap::empty()),
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn counters(&self) -> Counters {
+        Counters {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            live_bytes: self.live_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let slab_cached = self.slab_cached_bytes();
+        let mut heap = self.heap.lock();
+        HeapStats {
+            size: heap.size(),
+            used: heap.used(),
+            free: heap.free(),
+            largest_free_block: largest_free_block(&mut heap),
+            slab_cached,
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            live_bytes: self.live_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Locks the underlying heap, e.g. to read its statistics.
+    pub fn lock(&self) -> spin::MutexGuard<'_, Heap> {
+        self.heap.lock()
+    }
+
+    /// Bytes held by the size classes which are currently free.
+    pub fn slab_cached_bytes(&self) -> usize {
+        self.slabs.lock().cached_bytes()
+    }
+
+    /// Allocates straight from the linked list heap, growing it if needed.
+    pub(crate) fn alloc_backing(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        loop {
+            if let Ok(ptr) = heap.allocate_first_fit(layout) {
+                return ptr.as_ptr();
+            }
+
+            // The free space at the top of the heap is merged with the extension, so growing by
+            // the size plus worst case alignment padding is always enough.
+            if grow(&mut heap, layout.size() + layout.align()) == 0 {
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    /// Frees memory obtained from `alloc_backing`.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `alloc_backing` with the same `layout`.
+    pub(crate) unsafe fn dealloc_backing(&self, ptr: *mut u8, layout: Layout) {
+        unsafe {
+            self.heap
+                .lock()
+                .deallocate(NonNull::new_unchecked(ptr), layout)
+        }
+    }
+}
+
+impl GrowableHeap {
+    fn alloc_uncounted(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = slab::size_class(layout) else {
+            return self.alloc_backing(layout);
+        };
+
+        let mut slabs = self.slabs.lock();
+        if let Some(ptr) = slabs.pop(class) {
+            return ptr.as_ptr();
+        }
+
+        let Some(chunk) = NonNull::new(self.alloc_backing(slab::chunk_layout(class))) else {
+            return ptr::null_mut();
+        };
+        unsafe { slabs.refill(class, chunk) };
+        slabs.pop(class).map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc_uncounted(&self, ptr: *mut u8, layout: Layout) {
+        match slab::size_class(layout) {
+            Some(class) => unsafe {
+                self.slabs
+                    .lock()
+                    .push(class, NonNull::new_unchecked(ptr))
+            },
+            None => unsafe { self.dealloc_backing(ptr, layout) },
+        }
+    }
+}
+
+fn is_emergency(ptr: *mut u8) -> bool {
+    (EMERGENCY_POOL_START..EMERGENCY_POOL_START + EMERGENCY_POOL_SIZE).contains(&(ptr as usize))
+}
+
+/// Returns and clears the out of memory flag.
+pub fn take_out_of_memory() -> bool {
+    OUT_OF_MEMORY.swap(false, Ordering::SeqCst)
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut ptr = self.alloc_uncounted(layout);
+        if ptr.is_null() {
+            OUT_OF_MEMORY.store(true, Ordering::SeqCst);
+            ptr = self
+                .emergency
+                .lock()
+                .allocate_first_fit(layout)
+                .map_or(ptr::null_mut(), NonNull::as_ptr);
+            if !ptr.is_null() {
+                signals::raise(signals::SIGMEMORY);
+            }
+        }
+
+        if !ptr.is_null() {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            self.live_bytes.fetch_add(layout.size(), Ordering::Relaxed);
+            tracking::record(ptr, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        tracking::forget(ptr);
+        if is_emergency(ptr) {
+            unsafe {
+                self.emergency
+                    .lock()
+                    .deallocate(NonNull::new_unchecked(ptr), layout)
+            };
+        } else {
+            unsafe { self.dealloc_uncounted(ptr, layout) };
+        }
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // Blocks of a size class already have room for anything else in the same class.
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let class = slab::size_class(layout);
+        if class.is_some() && class == slab::size_class(new_layout) && !is_emergency(ptr) {
+            self.live_bytes.fetch_add(new_size, Ordering::Relaxed);
+            self.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+            tracking::forget(ptr);
+            tracking::record(ptr, new_size);
+            return ptr;
+        }
+
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+/// Finds the largest block that `heap` could hand out right now without growing.
+///
+/// `linked_list_allocator` does not expose its hole list, so this probes with a binary search.
+pub fn largest_free_block(heap: &mut Heap) -> usize {
+    const ALIGN: usize = 8;
+    let (mut lo, mut hi) = (0, heap.free() / ALIGN);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        let layout = Layout::from_size_align(mid * ALIGN, ALIGN).unwrap();
+        match heap.allocate_first_fit(layout) {
+            Ok(ptr) => {
+                unsafe { heap.deallocate(ptr, layout) };
+                lo = mid;
+            }
+            Err(()) => hi = mid - 1,
+        }
+    }
+    lo * ALIGN
+}
+
+/// Maps at least `bytes` more memory at the top of `heap` and extends it.
+///
+/// Returns the number of bytes actually added, which may be less than requested (or zero)
+/// if physical memory or the heap window runs out.
+fn grow(heap: &mut Heap, bytes: usize) -> usize {
+    let top = heap.top() as usize;
+    let limit = HEAP_START + HEAP_MAX_SIZE;
+
+    // Don't swallow all of physical memory for a request that can never succeed
+    let available = memory::with_frame_allocator(|frames| frames.free_frames()) as usize;
+    if bytes.next_multiple_of(PAGE_SIZE) / PAGE_SIZE > available {
+        return 0;
+    }
+
+    let wanted = bytes.max(MIN_GROWTH).next_multiple_of(PAGE_SIZE);
+    let wanted = wanted.min(available * PAGE_SIZE).min(limit - top);
+
+    let mut mapped = 0;
+    while mapped < wanted {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new((top + mapped) as u64));
+        if map_heap_page(page).is_err() {
+            break;
+        }
+        mapped += PAGE_SIZE;
+    }
+
+    if mapped > 0 {
+        unsafe { heap.extend(mapped) };
+    }
+
+    mapped
+}
+
+fn map_heap_page(page: Page) -> Result<(), MapToError<Size4KiB>> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::map_page(page, flags).map(|_| ())
+}
+
+fn map_heap_range(start: usize, size: usize) -> Result<(), MapToError<Size4KiB>> {
+    let first = Page::containing_address(VirtAddr::new(start as u64));
+    let last = Page::containing_address(VirtAddr::new((start + size - 1) as u64));
+    for page in Page::range_inclusive(first, last) {
+        map_heap_page(page)?;
+    }
+    Ok(())
+}
+
+/// Maps the initial heap and the emergency pool. Must be called after `memory::init` and
+/// before ANY allocations!
+pub fn init() -> Result<(), MapToError<Size4KiB>> {
+    map_heap_range(HEAP_START, INITIAL_HEAP_SIZE)?;
+    map_heap_range(EMERGENCY_POOL_START, EMERGENCY_POOL_SIZE)?;
+
+    unsafe {
+        ALLOCATOR
+            .lock()
+            .init(HEAP_START as *mut u8, INITIAL_HEAP_SIZE);
+        ALLOCATOR
+            .emergency
+            .lock()
+            .init(EMERGENCY_POOL_START as *mut u8, EMERGENCY_POOL_SIZE);
+    }
+
+    Ok(())
+}
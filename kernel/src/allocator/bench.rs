@@ -0,0 +1,164 @@
+//! Allocation benchmark, comparing the size classes with the bare linked list heap.
+//!
+//! The workload imitates an interpreter: mostly small objects with a long tail of larger
+//! buffers, freed in random order while a working set stays alive.
+//!
+//! Each run gets a fresh heap of its own, carved out of one large allocation, so neither run
+//! inherits the growth and fragmentation the other left behind.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::arch::x86_64::_rdtsc;
+use core::ptr::{self, NonNull};
+
+use linked_list_allocator::Heap;
+
+use super::slab::{self, Slabs};
+use super::{ALLOCATOR, largest_free_block};
+
+/// Number of allocations kept alive at any time.
+const LIVE_SET: usize = 2048;
+/// Size of the heap each run starts with.
+const ARENA_SIZE: usize = 16 * 1024 * 1024;
+
+/// Deterministic xorshift generator, so both runs see the same sequence.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Object sizes, roughly shaped like RustPython's allocations.
+    fn next_layout(&mut self) -> Layout {
+        let r = self.next();
+        let size = match r % 100 {
+            0..60 => 16 + r % 48,
+            60..85 => 64 + r % 192,
+            85..97 => 256 + r % 1792,
+            _ => 2048 + r % 14336,
+        };
+        Layout::from_size_align(size as usize, 8).unwrap()
+    }
+}
+
+struct RunResult {
+    cycles_per_op: u64,
+    heap_free: usize,
+    largest_block: usize,
+    slab_cached: usize,
+}
+
+/// A private heap for one run, optionally with size classes in front like `GrowableHeap`.
+struct Arena {
+    heap: Heap,
+    slabs: Option<Slabs>,
+}
+
+impl Arena {
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (Some(slabs), Some(class)) = (&mut self.slabs, slab::size_class(layout)) else {
+            return self.alloc_backing(layout);
+        };
+        if let Some(ptr) = slabs.pop(class) {
+            return ptr.as_ptr();
+        }
+        let chunk = self.heap.allocate_first_fit(slab::chunk_layout(class));
+        let Ok(chunk) = chunk else {
+            return ptr::null_mut();
+        };
+        unsafe { slabs.refill(class, chunk) };
+        slabs.pop(class).map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    fn alloc_backing(&mut self, layout: Layout) -> *mut u8 {
+        self.heap
+            .allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let ptr = unsafe { NonNull::new_unchecked(ptr) };
+        match (&mut self.slabs, slab::size_class(layout)) {
+            (Some(slabs), Some(class)) => unsafe { slabs.push(class, ptr) },
+            _ => unsafe { self.heap.deallocate(ptr, layout) },
+        }
+    }
+}
+
+fn run_workload(ops: usize, arena: &mut Arena) -> RunResult {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut live: Vec<Option<(*mut u8, Layout)>> = vec![None; LIVE_SET];
+
+    let start = unsafe { _rdtsc() };
+    for _ in 0..ops {
+        let slot = &mut live[rng.next() as usize % LIVE_SET];
+        if let Some((ptr, layout)) = slot.take() {
+            unsafe { arena.dealloc(ptr, layout) };
+        }
+
+        let layout = rng.next_layout();
+        let ptr = arena.alloc(layout);
+        if !ptr.is_null() {
+            *slot = Some((ptr, layout));
+        }
+    }
+    let end = unsafe { _rdtsc() };
+
+    // Measure fragmentation while the working set is still alive
+    let heap_free = arena.heap.free();
+    let largest_block = largest_free_block(&mut arena.heap);
+    let slab_cached = arena.slabs.as_ref().map_or(0, Slabs::cached_bytes);
+
+    for (ptr, layout) in live.into_iter().flatten() {
+        unsafe { arena.dealloc(ptr, layout) };
+    }
+
+    RunResult {
+        cycles_per_op: (end - start) / ops.max(1) as u64,
+        heap_free,
+        largest_block,
+        slab_cached,
+    }
+}
+
+fn report(name: &str, result: &RunResult) {
+    let fragmentation = match result.heap_free {
+        0 => 0,
+        free => 100 - result.largest_block * 100 / free,
+    };
+    println!("  {name:<12} {:>6} cycles/op", result.cycles_per_op);
+    println!(
+        "  {:<12} heap free {} KiB, largest block {} KiB ({fragmentation}% fragmented), {} KiB in size classes",
+        "",
+        result.heap_free >> 10,
+        result.largest_block >> 10,
+        result.slab_cached >> 10,
+    );
+}
+
+/// Runs the benchmark `ops` times against both allocation paths and prints the results.
+pub fn run(ops: usize) {
+    println!("alloc_bench: {ops} operations, {LIVE_SET} live objects");
+
+    let layout = Layout::from_size_align(ARENA_SIZE, 4096).unwrap();
+    let memory = ALLOCATOR.alloc_backing(layout);
+    if memory.is_null() {
+        println!("  not enough memory for a {} MiB arena", ARENA_SIZE >> 20);
+        return;
+    }
+
+    for (name, slabs) in [("size classes", Some(Slabs::new())), ("linked list", None)] {
+        let mut arena = Arena {
+            heap: unsafe { Heap::new(memory, ARENA_SIZE) },
+            slabs,
+        };
+        report(name, &run_workload(ops, &mut arena));
+    }
+
+    unsafe { ALLOCATOR.dealloc_backing(memory, layout) };
+}
@@ -0,0 +1,92 @@
+//! Size-class free lists in front of the linked list heap.
+//!
+//! RustPython allocates huge numbers of small, short lived objects. Serving those from
+//! per-size free lists makes allocation O(1) and keeps the linked list heap from fragmenting
+//! into thousands of tiny holes. Memory handed to a size class is never given back to the
+//! linked list heap, it is simply reused by the next allocation of that class.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Block sizes of the size classes. Each block is aligned to its own size.
+pub const CLASS_SIZES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Size of the chunks that are carved up into blocks when a class runs empty.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+pub struct Slabs {
+    free: [Option<NonNull<FreeBlock>>; CLASS_SIZES.len()],
+}
+
+// The free blocks are owned by the heap, which is behind a lock.
+unsafe impl Send for Slabs {}
+
+/// Returns the index of the size class which serves `layout`, if any.
+pub fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    CLASS_SIZES
+        .iter()
+        .position(|&class_size| class_size >= size)
+}
+
+/// Layout of the chunk used to refill `class`.
+pub fn chunk_layout(class: usize) -> Layout {
+    Layout::from_size_align(CHUNK_SIZE, CLASS_SIZES[class]).unwrap()
+}
+
+impl Slabs {
+    pub const fn new() -> Self {
+        Self {
+            free: [None; CLASS_SIZES.len()],
+        }
+    }
+
+    /// Takes a block of `class` off its free list.
+    pub fn pop(&mut self, class: usize) -> Option<NonNull<u8>> {
+        let block = self.free[class]?;
+        self.free[class] = unsafe { block.as_ref().next };
+        Some(block.cast())
+    }
+
+    /// Puts a block back on the free list of `class`.
+    ///
+    /// # Safety
+    /// `ptr` must be an unused block that was handed out for `class`.
+    pub unsafe fn push(&mut self, class: usize, ptr: NonNull<u8>) {
+        let block = ptr.cast::<FreeBlock>();
+        unsafe {
+            block.as_ptr().write(FreeBlock {
+                next: self.free[class],
+            })
+        };
+        self.free[class] = Some(block);
+    }
+
+    /// Splits a chunk allocated with `chunk_layout(class)` into blocks of `class`.
+    ///
+    /// # Safety
+    /// `chunk` must be a fresh allocation of `chunk_layout(class)`.
+    pub unsafe fn refill(&mut self, class: usize, chunk: NonNull<u8>) {
+        let class_size = CLASS_SIZES[class];
+        for offset in (0..CHUNK_SIZE).step_by(class_size).rev() {
+            unsafe { self.push(class, chunk.add(offset)) };
+        }
+    }
+
+    /// Bytes sitting unused on the free lists.
+    pub fn cached_bytes(&self) -> usize {
+        let mut total = 0;
+        for (class, head) in self.free.iter().enumerate() {
+            let mut next = *head;
+            while let Some(block) = next {
+                total += CLASS_SIZES[class];
+                next = unsafe { block.as_ref().next };
+            }
+        }
+        total
+    }
+}
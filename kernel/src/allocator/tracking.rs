@@ -0,0 +1,223 @@
+//! Optional bookkeeping of live allocations by call site, for hunting leaks.
+//!
+//! While enabled, every allocation is recorded in a fixed size hash table together with the
+//! return addresses of its callers, found by walking the frame pointer chain. The addresses can
+//! be resolved on the host with `addr2line -e target/x86_64-blog_os/debug/python_os`.
+//!
+//! Nothing in here allocates, since it runs inside the global allocator.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+/// Number of return addresses recorded per allocation.
+pub const SITE_DEPTH: usize = 6;
+/// Maximum number of live allocations that can be tracked at once.
+const TABLE_SIZE: usize = 1 << 16;
+/// Maximum number of distinct call sites `largest_sites` can tell apart.
+const MAX_SITES: usize = 512;
+/// Frames belonging to the allocator itself, which are not interesting.
+const SKIP_FRAMES: usize = 2;
+/// A saved frame pointer further away than this is assumed to be garbage.
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+pub type Site = [usize; SITE_DEPTH];
+
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Address of the allocation, zero if the slot is empty.
+    ptr: usize,
+    size: usize,
+    site: Site,
+}
+
+const EMPTY: Entry = Entry {
+    ptr: 0,
+    size: 0,
+    site: [0; SITE_DEPTH],
+};
+
+/// Live bytes and allocation count of one call site.
+#[derive(Clone, Copy, Default)]
+pub struct SiteTotal {
+    pub site: Site,
+    pub bytes: usize,
+    pub count: usize,
+}
+
+struct Table {
+    entries: [Entry; TABLE_SIZE],
+    totals: [SiteTotal; MAX_SITES],
+}
+
+static TABLE: Mutex<Table> = Mutex::new(Table {
+    entries: [EMPTY; TABLE_SIZE],
+    totals: [SiteTotal {
+        site: [0; SITE_DEPTH],
+        bytes: 0,
+        count: 0,
+    }; MAX_SITES],
+});
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Number of occupied table slots, so `forget` can skip the lock when nothing is tracked.
+static TRACKED: AtomicUsize = AtomicUsize::new(0);
+/// Allocations that were not tracked because the table was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Allocations made while tracking that did not fit into the table.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+fn slot_of(ptr: usize) -> usize {
+    // Allocations are at least 8 byte aligned, so the low bits carry no information
+    (ptr >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - TABLE_SIZE.trailing_zeros())
+}
+
+/// Return addresses of the callers of the allocator, innermost first.
+#[inline(never)]
+fn call_site() -> Site {
+    let mut rbp: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp) };
+
+    let mut site = [0; SITE_DEPTH];
+    let mut depth = 0;
+    let mut skipped = 0;
+    while depth < SITE_DEPTH && rbp != 0 && rbp % 8 == 0 {
+        let (next, return_address) = unsafe {
+            let frame = rbp as *const usize;
+            (*frame, *frame.add(1))
+        };
+
+        if skipped < SKIP_FRAMES {
+            skipped += 1;
+        } else {
+            site[depth] = return_address;
+            depth += 1;
+        }
+
+        // The stack grows down, so callers' frames must be above ours
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+
+    site
+}
+
+/// Records a fresh allocation, if tracking is enabled.
+#[inline(never)]
+pub fn record(ptr: *mut u8, size: usize) {
+    if !enabled() {
+        return;
+    }
+
+    let site = call_site();
+    let mut table = TABLE.lock();
+    let mut slot = slot_of(ptr as usize);
+    for _ in 0..TABLE_SIZE {
+        let entry = &mut table.entries[slot];
+        if entry.ptr == 0 {
+            *entry = Entry {
+                ptr: ptr as usize,
+                size,
+                site,
+            };
+            TRACKED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        slot = (slot + 1) % TABLE_SIZE;
+    }
+
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Removes a freed allocation from the table, if it was tracked.
+pub fn forget(ptr: *mut u8) {
+    if TRACKED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let mut table = TABLE.lock();
+    let entries = &mut table.entries;
+    let mut slot = slot_of(ptr as usize);
+    loop {
+        match entries[slot].ptr {
+            0 => return,
+            p if p == ptr as usize => break,
+            _ => slot = (slot + 1) % TABLE_SIZE,
+        }
+    }
+
+    // Backward shift deletion, which keeps linear probing correct without tombstones
+    let mut hole = slot;
+    let mut next = (hole + 1) % TABLE_SIZE;
+    while entries[next].ptr != 0 {
+        let home = slot_of(entries[next].ptr);
+        // Move the entry into the hole unless its home slot lies cyclically in (hole, next]
+        let stays = if hole <= next {
+            hole < home && home <= next
+        } else {
+            hole < home || home <= next
+        };
+        if !stays {
+            entries[hole] = entries[next];
+            hole = next;
+        }
+        next = (next + 1) % TABLE_SIZE;
+    }
+    entries[hole] = EMPTY;
+    TRACKED.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Stops tracking everything that is currently live.
+pub fn clear() {
+    let mut table = TABLE.lock();
+    table.entries.fill(EMPTY);
+    TRACKED.store(0, Ordering::Relaxed);
+    DROPPED.store(0, Ordering::Relaxed);
+}
+
+/// The `N` call sites holding the most live bytes, largest first.
+///
+/// Sites beyond the first `MAX_SITES` distinct ones are not counted.
+pub fn largest_sites<const N: usize>() -> [SiteTotal; N] {
+    let mut guard = TABLE.lock();
+    let table = &mut *guard;
+    let mut site_count = 0;
+
+    for entry in table.entries.iter().filter(|entry| entry.ptr != 0) {
+        let totals = &mut table.totals[..site_count];
+        if let Some(total) = totals.iter_mut().find(|total| total.site == entry.site) {
+            total.bytes += entry.size;
+            total.count += 1;
+        } else if site_count < MAX_SITES {
+            table.totals[site_count] = SiteTotal {
+                site: entry.site,
+                bytes: entry.size,
+                count: 1,
+            };
+            site_count += 1;
+        }
+    }
+
+    let totals = &mut table.totals[..site_count];
+    totals.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes));
+
+    let mut largest = [SiteTotal::default(); N];
+    for (dst, src) in largest.iter_mut().zip(totals.iter()) {
+        *dst = *src;
+    }
+    largest
+}
@@ -0,0 +1,56 @@
+//! What the kernel is told by whichever loader started it.
+//!
+//! The kernel can be started by bootloader 0.11 (`kernel_main`) or by a Multiboot loader such
+//! as GRUB or `qemu -kernel` (see `multiboot`). Both translate their boot information into
+//! `BootParams` and continue in `crate::start`.
+
+use bootloader_api::info::{FrameBuffer, MemoryRegions};
+
+pub struct BootParams {
+    pub memory_regions: &'static MemoryRegions,
+    /// The virtual address at which all of physical memory is mapped.
+    pub physical_memory_offset: u64,
+    /// Where to print to, or `None` when headless: there is no screen and nothing gets drawn.
+    pub console: Option<Console>,
+    /// Physical address of the ACPI root table pointer, if the loader provided one.
+    pub rsdp_address: Option<u64>,
+    /// The kernel command line, if the loader has a way to pass one.
+    pub cmdline: Option<&'static str>,
+    /// Files loaded alongside the kernel, e.g. an initrd.
+    pub modules: &'static [BootModule],
+    /// The stack the kernel was entered on.
+    pub stack: BootStack,
+}
+
+/// Where to print to.
+pub enum Console {
+    Framebuffer(&'static mut FrameBuffer),
+    /// The VGA text buffer at physical address `0xb8000`.
+    VgaText,
+}
+
+/// A file the loader placed in memory for us.
+#[derive(Clone, Copy)]
+pub struct BootModule {
+    /// The module's command line, or its file name.
+    pub name: &'static str,
+    pub data: &'static [u8],
+}
+
+pub enum BootStack {
+    /// The stack set up by bootloader 0.11 at `stack::KERNEL_STACK_ADDRESS`.
+    Bootloader,
+    /// A stack inside the kernel image, given as `bottom..top`.
+    Static { bottom: u64, top: u64 },
+}
+
+static MODULES: spin::Once<&'static [BootModule]> = spin::Once::new();
+
+pub fn init(modules: &'static [BootModule]) {
+    MODULES.call_once(|| modules);
+}
+
+/// Files loaded alongside the kernel.
+pub fn modules() -> &'static [BootModule] {
+    MODULES.get().copied().unwrap_or(&[])
+}
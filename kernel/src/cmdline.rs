@@ -0,0 +1,118 @@
+//! The kernel command line.
+//!
+//! It comes from the Multiboot loader, or otherwise from the QEMU firmware configuration file
+//! `opt/python_os/cmdline`. Words of the form `key=value`, and the flags in `FLAGS`, are boot
+//! options. All other words, and everything after a `--`, are arguments for Python's
+//! `sys.argv`. Values may be put in double quotes to include spaces.
+//!
+//! Nothing here allocates, so options can be read before the heap exists.
+//!
+//! Boot options:
+//! * `init=<module>`: run the boot module with this name (or file name) before the REPL
+//! * `fg=<color>`, `bg=<color>`: console colors, e.g. `fg=light_green`
+//! * `keymap=<layout>`: keyboard layout, one of `us`, `uk`, `de`, `azerty`, `dvorak`, `colemak`
+//!   or `jis`
+//! * `stack=<size>`: size of the interpreter's stack, e.g. `16M` (default `8M`, at least `1M`)
+//! * `quiet`: don't print boot messages
+
+use spin::Once;
+
+use crate::fw_cfg;
+
+const FW_CFG_FILE: &str = "opt/python_os/cmdline";
+const MAX_FW_CFG_LEN: usize = 4096;
+
+/// Words that are boot options even without a value.
+const FLAGS: &[&str] = &["quiet"];
+
+static CMDLINE: Once<&'static str> = Once::new();
+
+/// Picks up the command line, preferring the one given by the loader.
+pub fn init(from_loader: Option<&'static str>) {
+    CMDLINE.call_once(|| from_loader.or_else(read_fw_cfg).unwrap_or("").trim());
+}
+
+fn read_fw_cfg() -> Option<&'static str> {
+    static mut BUFFER: [u8; MAX_FW_CFG_LEN] = [0; MAX_FW_CFG_LEN];
+    let buffer = unsafe { &mut BUFFER };
+    let len = fw_cfg::read_file(FW_CFG_FILE, buffer)?;
+    core::str::from_utf8(&buffer[..len])
+        .ok()
+        .map(|s| s.trim_end_matches('\0'))
+}
+
+/// The whole command line.
+pub fn get() -> &'static str {
+    CMDLINE.get().copied().unwrap_or("")
+}
+
+/// The value of the boot option `key`, if it was given.
+pub fn option(key: &str) -> Option<&'static str> {
+    options().find_map(|(k, value)| (k == key).then_some(value).flatten())
+}
+
+/// Whether the flag `name` was given.
+pub fn flag(name: &str) -> bool {
+    options().any(|(k, value)| k == name && value.is_none())
+}
+
+/// Arguments that are not boot options.
+pub fn args() -> impl Iterator<Item = &'static str> {
+    let mut after_separator = false;
+    words().filter_map(move |word| {
+        if after_separator {
+            return Some(unquote(word));
+        }
+        if word == "--" {
+            after_separator = true;
+            return None;
+        }
+        parse_option(word).is_none().then(|| unquote(word))
+    })
+}
+
+fn options() -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    words()
+        .take_while(|&word| word != "--")
+        .filter_map(parse_option)
+}
+
+fn parse_option(word: &'static str) -> Option<(&'static str, Option<&'static str>)> {
+    match word.split_once('=') {
+        Some((key, value)) if !key.starts_with('"') => Some((key, Some(unquote(value)))),
+        _ if FLAGS.contains(&word) => Some((word, None)),
+        _ => None,
+    }
+}
+
+/// Splits the command line at whitespace outside of double quotes.
+fn words() -> impl Iterator<Item = &'static str> {
+    let mut rest = get();
+    core::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut quoted = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c.is_whitespace() && !quoted
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(rest.len());
+        let (word, remainder) = rest.split_at(end);
+        rest = remainder;
+        Some(word)
+    })
+}
+
+fn unquote(s: &'static str) -> &'static str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
@@ -0,0 +1,170 @@
+//! CPU feature detection, and setting up the x87, SSE and AVX units.
+
+use core::arch::x86_64::{__cpuid, __cpuid_count, CpuidResult};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+/// MXCSR with every exception masked and rounding to nearest, the state everyone expects.
+pub const DEFAULT_MXCSR: u32 = 0x1F80;
+
+/// The MXCSR exception flags, by bit. The mask of each one is 7 bits higher.
+pub const MXCSR_EXCEPTIONS: [&str; 6] = [
+    "invalid operation",
+    "denormal operand",
+    "divide by zero",
+    "overflow",
+    "underflow",
+    "precision",
+];
+
+// XCR0 bits, i.e. state components managed by XSAVE
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+
+#[derive(Clone, Copy)]
+enum Register {
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// Features we look for, named like in Linux's `/proc/cpuinfo`: the CPUID leaf, the register
+/// and the bit reporting each one.
+const FEATURES: [(&str, u32, Register, u8); 43] = {
+    use Register::*;
+    [
+        ("fpu", 1, Edx, 0),
+        ("tsc", 1, Edx, 4),
+        ("msr", 1, Edx, 5),
+        ("pae", 1, Edx, 6),
+        ("apic", 1, Edx, 9),
+        ("pge", 1, Edx, 13),
+        ("pat", 1, Edx, 16),
+        ("clflush", 1, Edx, 19),
+        ("mmx", 1, Edx, 23),
+        ("fxsr", 1, Edx, 24),
+        ("sse", 1, Edx, 25),
+        ("sse2", 1, Edx, 26),
+        ("sse3", 1, Ecx, 0),
+        ("pclmulqdq", 1, Ecx, 1),
+        ("ssse3", 1, Ecx, 9),
+        ("fma", 1, Ecx, 12),
+        ("cx16", 1, Ecx, 13),
+        ("sse4_1", 1, Ecx, 19),
+        ("sse4_2", 1, Ecx, 20),
+        ("x2apic", 1, Ecx, 21),
+        ("movbe", 1, Ecx, 22),
+        ("popcnt", 1, Ecx, 23),
+        ("aes", 1, Ecx, 25),
+        ("xsave", 1, Ecx, 26),
+        ("avx", 1, Ecx, 28),
+        ("f16c", 1, Ecx, 29),
+        ("rdrand", 1, Ecx, 30),
+        ("hypervisor", 1, Ecx, 31),
+        ("fsgsbase", 7, Ebx, 0),
+        ("bmi1", 7, Ebx, 3),
+        ("avx2", 7, Ebx, 5),
+        ("smep", 7, Ebx, 7),
+        ("bmi2", 7, Ebx, 8),
+        ("erms", 7, Ebx, 9),
+        ("avx512f", 7, Ebx, 16),
+        ("rdseed", 7, Ebx, 18),
+        ("adx", 7, Ebx, 19),
+        ("smap", 7, Ebx, 20),
+        ("sha_ni", 7, Ebx, 29),
+        ("umip", 7, Ecx, 2),
+        ("nx", 0x8000_0001, Edx, 20),
+        ("pdpe1gb", 0x8000_0001, Edx, 26),
+        ("rdtscp", 0x8000_0001, Edx, 27),
+    ]
+};
+
+/// Bit `i` is set if the CPU has `FEATURES[i]`.
+static DETECTED: AtomicU64 = AtomicU64::new(0);
+
+fn cpuid(leaf: u32) -> Option<CpuidResult> {
+    // The highest leaf is reported separately for the basic and the extended leaves
+    let max_leaf = unsafe { __cpuid(leaf & 0x8000_0000) }.eax;
+    (leaf <= max_leaf).then(|| unsafe { __cpuid_count(leaf, 0) })
+}
+
+fn detect() -> u64 {
+    let mut detected = 0;
+    for (index, &(_, leaf, register, bit)) in FEATURES.iter().enumerate() {
+        let Some(result) = cpuid(leaf) else {
+            continue;
+        };
+        let value = match register {
+            Register::Ebx => result.ebx,
+            Register::Ecx => result.ecx,
+            Register::Edx => result.edx,
+        };
+        if value & (1 << bit) != 0 {
+            detected |= 1 << index;
+        }
+    }
+    detected
+}
+
+/// Whether the CPU has `feature`, one of the names in `FEATURES`.
+pub fn has(feature: &str) -> bool {
+    features().any(|name| name == feature)
+}
+
+/// The names of the features the CPU has.
+pub fn features() -> impl Iterator<Item = &'static str> {
+    let detected = DETECTED.load(Ordering::Relaxed);
+    FEATURES
+        .iter()
+        .enumerate()
+        .filter(move |&(index, _)| detected & (1 << index) != 0)
+        .map(|(_, &(name, ..))| name)
+}
+
+/// Whether AVX was enabled by `init`.
+pub fn avx_enabled() -> bool {
+    has("avx") && has("xsave")
+}
+
+/// Detects features, and sets up the FPU and SIMD units. Must run before any floating point or
+/// SIMD instruction.
+pub fn init() {
+    DETECTED.store(detect(), Ordering::Relaxed);
+
+    let mut cr0 = Cr0::read();
+    cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
+    cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+    // Report x87 errors as #MF rather than through the legacy IRQ13
+    cr0.insert(Cr0Flags::NUMERIC_ERROR);
+    unsafe { Cr0::write(cr0) };
+
+    let mut cr4 = Cr4::read();
+    // FXSAVE/FXRSTOR, and #XM for unmasked SIMD floating point exceptions instead of #UD
+    cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+    if has("xsave") {
+        cr4.insert(Cr4Flags::OSXSAVE);
+    }
+    unsafe { Cr4::write(cr4) };
+
+    if has("xsave") {
+        let mut xcr0 = XCR0_X87 | XCR0_SSE;
+        if has("avx") {
+            xcr0 |= XCR0_AVX;
+        }
+        unsafe {
+            core::arch::asm!(
+                "xsetbv",
+                in("ecx") 0,
+                in("eax") xcr0 as u32,
+                in("edx") (xcr0 >> 32) as u32,
+                options(nomem, nostack),
+            );
+        }
+    }
+
+    unsafe {
+        core::arch::asm!("fninit", options(nomem, nostack));
+        core::arch::asm!("ldmxcsr [{}]", in(reg) &DEFAULT_MXCSR, options(readonly, nostack));
+    }
+}
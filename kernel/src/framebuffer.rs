@@ -0,0 +1,114 @@
+//! Drawing text onto a linear framebuffer.
+//!
+//! Both the BIOS and the UEFI boot paths of the bootloader switch to a graphics mode, so the
+//! VGA text buffer is not available. This renders the same grid of characters using the
+//! Noto Sans Mono bitmap font instead.
+
+use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
+use noto_sans_mono_bitmap::{FontWeight, RasterHeight, get_raster, get_raster_width};
+
+use crate::vga_buffer::Color;
+
+const FONT_WEIGHT: FontWeight = FontWeight::Regular;
+const CHAR_HEIGHT: RasterHeight = RasterHeight::Size16;
+const CHAR_WIDTH: usize = get_raster_width(FONT_WEIGHT, CHAR_HEIGHT);
+/// Height of the cursor underline, in pixels.
+const CURSOR_HEIGHT: usize = 2;
+
+pub struct FramebufferConsole {
+    buffer: &'static mut [u8],
+    info: FrameBufferInfo,
+}
+
+impl FramebufferConsole {
+    pub fn new(framebuffer: &'static mut FrameBuffer) -> Self {
+        let info = framebuffer.info();
+        let mut console = Self {
+            buffer: framebuffer.buffer_mut(),
+            info,
+        };
+        console.buffer.fill(0);
+        console
+    }
+
+    /// Width of the screen in characters.
+    pub fn columns(&self) -> usize {
+        self.info.width / CHAR_WIDTH
+    }
+
+    /// Height of the screen in characters.
+    pub fn rows(&self) -> usize {
+        self.info.height / CHAR_HEIGHT.val()
+    }
+
+    fn pixel_bytes(&self, (r, g, b): (u8, u8, u8)) -> [u8; 4] {
+        match self.info.pixel_format {
+            PixelFormat::Rgb => [r, g, b, 0],
+            PixelFormat::Bgr => [b, g, r, 0],
+            // Grayscale, or something we don't understand
+            _ => {
+                let luma = ((r as u16 * 3 + g as u16 * 6 + b as u16) / 10) as u8;
+                [luma; 4]
+            }
+        }
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let offset = (y * self.info.stride + x) * bytes_per_pixel;
+        let len = bytes_per_pixel.min(pixel.len());
+        self.buffer[offset..offset + len].copy_from_slice(&pixel[..len]);
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let pixel = self.pixel_bytes(color.rgb());
+        for y in y..y + height {
+            for x in x..x + width {
+                self.put_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    /// Draws `c` into the character cell at `column`, `row`.
+    pub fn draw_char(&mut self, column: usize, row: usize, c: char, fg: Color, bg: Color) {
+        let raster = get_raster(c, FONT_WEIGHT, CHAR_HEIGHT)
+            .or_else(|| get_raster('\u{fffd}', FONT_WEIGHT, CHAR_HEIGHT))
+            .unwrap();
+        let (x0, y0) = (column * CHAR_WIDTH, row * CHAR_HEIGHT.val());
+
+        let (fg, bg) = (fg.rgb(), bg.rgb());
+        for (y, line) in raster.raster().iter().enumerate() {
+            for (x, &intensity) in line.iter().enumerate() {
+                let blend = |f: u8, b: u8| {
+                    ((f as u16 * intensity as u16 + b as u16 * (255 - intensity as u16)) / 255)
+                        as u8
+                };
+                let pixel =
+                    self.pixel_bytes((blend(fg.0, bg.0), blend(fg.1, bg.1), blend(fg.2, bg.2)));
+                self.put_pixel(x0 + x, y0 + y, pixel);
+            }
+        }
+    }
+
+    /// Fills a whole row of characters with `bg`.
+    pub fn clear_row(&mut self, row: usize, bg: Color) {
+        let width = self.columns() * CHAR_WIDTH;
+        self.fill_rect(0, row * CHAR_HEIGHT.val(), width, CHAR_HEIGHT.val(), bg);
+    }
+
+    /// Moves every row of characters up by one. The last row is left as it was.
+    pub fn scroll_up(&mut self) {
+        let row_bytes = self.info.stride * self.info.bytes_per_pixel * CHAR_HEIGHT.val();
+        let text_bytes = row_bytes * self.rows();
+        self.buffer.copy_within(row_bytes..text_bytes, 0);
+    }
+
+    /// Draws or erases the underline cursor in the character cell at `column`, `row`.
+    pub fn draw_cursor(&mut self, column: usize, row: usize, color: Color) {
+        if column >= self.columns() || row >= self.rows() {
+            return;
+        }
+        let y = (row + 1) * CHAR_HEIGHT.val() - CURSOR_HEIGHT;
+        self.fill_rect(column * CHAR_WIDTH, y, CHAR_WIDTH, CURSOR_HEIGHT, color);
+    }
+}
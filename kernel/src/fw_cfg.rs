@@ -0,0 +1,70 @@
+//! QEMU's firmware configuration device, see https://www.qemu.org/docs/master/specs/fw_cfg.html
+//!
+//! Lets the host hand us named files, e.g. with `-fw_cfg name=opt/python_os/cmdline,string=...`.
+
+use x86_64::instructions::port::Port;
+
+const SELECTOR_PORT: u16 = 0x510;
+const DATA_PORT: u16 = 0x511;
+
+const SELECT_SIGNATURE: u16 = 0x0000;
+const SELECT_FILE_DIR: u16 = 0x0019;
+
+/// Length of the name field in a file directory entry, including the NUL terminator.
+const FILE_NAME_LEN: usize = 56;
+
+fn select(key: u16) {
+    unsafe { Port::new(SELECTOR_PORT).write(key) };
+}
+
+fn read_bytes(buffer: &mut [u8]) {
+    let mut data = Port::<u8>::new(DATA_PORT);
+    for byte in buffer {
+        *byte = unsafe { data.read() };
+    }
+}
+
+fn read_be_u32() -> u32 {
+    let mut bytes = [0; 4];
+    read_bytes(&mut bytes);
+    u32::from_be_bytes(bytes)
+}
+
+/// Whether we are running under QEMU with the device present.
+pub fn present() -> bool {
+    let mut signature = [0; 4];
+    select(SELECT_SIGNATURE);
+    read_bytes(&mut signature);
+    &signature == b"QEMU"
+}
+
+/// Reads the file `name` into `buffer`. Returns how many bytes were read, which is less than the
+/// file size if the file doesn't fit.
+pub fn read_file(name: &str, buffer: &mut [u8]) -> Option<usize> {
+    if !present() {
+        return None;
+    }
+
+    select(SELECT_FILE_DIR);
+    let count = read_be_u32();
+    for _ in 0..count {
+        let size = read_be_u32() as usize;
+        let mut header = [0; 4];
+        read_bytes(&mut header);
+        let key = u16::from_be_bytes([header[0], header[1]]);
+        let mut file_name = [0; FILE_NAME_LEN];
+        read_bytes(&mut file_name);
+
+        let len = file_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(FILE_NAME_LEN);
+        if &file_name[..len] == name.as_bytes() {
+            let len = size.min(buffer.len());
+            select(key);
+            read_bytes(&mut buffer[..len]);
+            return Some(len);
+        }
+    }
+    None
+}
@@ -0,0 +1,106 @@
+//! Global descriptor table and task state segment.
+//!
+//! Replaces whatever the bootloader left behind with our own: kernel code and data segments,
+//! user (ring 3) code and data segments, and a TSS providing known good stacks for interrupts.
+//! The user segments come right after the kernel ones, in the order `sysret` expects.
+
+use lazy_static::lazy_static;
+use x86_64::VirtAddr;
+use x86_64::instructions::segmentation::{CS, DS, ES, FS, GS, SS, Segment};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+
+/// Interrupt stack used by the double fault handler, so it still works after the kernel stack
+/// overflowed.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Interrupt stacks for the NMI and machine check handlers, which can interrupt anything,
+/// including code that is in the middle of switching stacks.
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const IST_STACK_SIZE: usize = 4096 * 5;
+/// Size of the stack the CPU switches to when an interrupt arrives in ring 3.
+const PRIVILEGE_STACK_SIZE: usize = 4096 * 16;
+
+/// A statically allocated stack, aligned like the CPU aligns interrupt stack frames.
+#[repr(C, align(16))]
+struct InterruptStack<const SIZE: usize>([u8; SIZE]);
+
+/// The initial stack pointer of `stack`, the stack grows down from here.
+fn top<const SIZE: usize>(stack: *const InterruptStack<SIZE>) -> VirtAddr {
+    VirtAddr::from_ptr(stack) + SIZE
+}
+
+// `static mut` so they end up in writable memory
+static mut DOUBLE_FAULT_STACK: InterruptStack<IST_STACK_SIZE> = InterruptStack([0; IST_STACK_SIZE]);
+static mut NMI_STACK: InterruptStack<IST_STACK_SIZE> = InterruptStack([0; IST_STACK_SIZE]);
+static mut MACHINE_CHECK_STACK: InterruptStack<IST_STACK_SIZE> =
+    InterruptStack([0; IST_STACK_SIZE]);
+static mut PRIVILEGE_STACK: InterruptStack<PRIVILEGE_STACK_SIZE> =
+    InterruptStack([0; PRIVILEGE_STACK_SIZE]);
+
+lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            top(&raw const DOUBLE_FAULT_STACK);
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = top(&raw const NMI_STACK);
+        tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] =
+            top(&raw const MACHINE_CHECK_STACK);
+        tss.privilege_stack_table[0] = top(&raw const PRIVILEGE_STACK);
+        tss
+    };
+}
+
+// Nothing runs in user mode yet
+#[allow(dead_code)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        (
+            gdt,
+            Selectors {
+                kernel_code,
+                kernel_data,
+                user_data,
+                user_code,
+                tss,
+            },
+        )
+    };
+}
+
+/// The segment selectors of our GDT.
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// Loads the GDT and TSS, and reloads all segment registers.
+pub fn init() {
+    GDT.0.load();
+    let selectors = selectors();
+    unsafe {
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        // Only their base addresses matter in long mode, which live in MSRs
+        FS::set_reg(SegmentSelector(0));
+        GS::set_reg(SegmentSelector(0));
+        load_tss(selectors.tss);
+    }
+}
@@ -0,0 +1,507 @@
+//! Interrupt descriptor table and exception handlers.
+//!
+//! Every architectural exception enters through a small assembly stub, which saves all general
+//! purpose registers and the SSE state into an `ExceptionFrame` before calling
+//! `exception_handler`. Unlike the `x86-interrupt` ABI, this gives the handler the complete
+//! register state, which is printed to the screen and the serial port before halting. Faults
+//! inside `recovery::catch_fault` return to it instead.
+
+use core::fmt::Write;
+
+use lazy_static::lazy_static;
+use pic8259::ChainedPics;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::hlt;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{
+    HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+};
+
+use crate::{cpu, gdt, irq, keyboard, recovery, serial, stack, timer, vga_buffer};
+
+/// Hardware interrupts are remapped to the vectors right after the CPU exceptions.
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+pub static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Interrupt vectors of the hardware interrupts we handle.
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard = PIC_1_OFFSET + 1,
+}
+
+impl InterruptIndex {
+    fn as_usize(self) -> usize {
+        self as usize
+    }
+
+    /// The PIC input line of this interrupt.
+    fn irq(self) -> u8 {
+        self as u8 - PIC_1_OFFSET
+    }
+}
+
+/// Registers saved by `exception_common`. Layout is shared with the assembly below.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExceptionFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// Zero for exceptions that don't push an error code.
+    pub error_code: u64,
+    // Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// The x87 and SSE state saved by `exception_common` with `fxsave`, right below the frame.
+#[repr(C, align(16))]
+pub struct FxSaveArea {
+    _x87_control: [u8; 24],
+    pub mxcsr: u32,
+    _rest: [u8; 484],
+}
+
+core::arch::global_asm!(
+    r#"
+.macro exception_stub vector
+exception_stub_\vector:
+    push 0
+    push \vector
+    jmp exception_common
+.endm
+
+/* For the exceptions where the CPU pushes an error code itself */
+.macro exception_stub_with_error vector
+exception_stub_\vector:
+    push \vector
+    jmp exception_common
+.endm
+
+exception_stub 0
+exception_stub 1
+exception_stub 2
+exception_stub 3
+exception_stub 4
+exception_stub 5
+exception_stub 6
+exception_stub 7
+exception_stub_with_error 8
+exception_stub 9
+exception_stub_with_error 10
+exception_stub_with_error 11
+exception_stub_with_error 12
+exception_stub_with_error 13
+exception_stub_with_error 14
+exception_stub 16
+exception_stub_with_error 17
+exception_stub 18
+exception_stub 19
+exception_stub 20
+exception_stub_with_error 29
+exception_stub_with_error 30
+
+exception_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    /* 22 quadwords were pushed onto the 16 byte aligned interrupt stack, so it still is */
+    sub rsp, 512
+    fxsave [rsp]
+    lea rdi, [rsp + 512]
+    mov rsi, rsp
+    cld
+    call exception_handler
+    fxrstor [rsp]
+    add rsp, 512
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    /* Vector and error code */
+    add rsp, 16
+    iretq
+
+.pushsection .rodata
+.global exception_stubs
+.align 8
+exception_stubs:
+    .quad exception_stub_0, exception_stub_1, exception_stub_2, exception_stub_3
+    .quad exception_stub_4, exception_stub_5, exception_stub_6, exception_stub_7
+    .quad exception_stub_8, exception_stub_9, exception_stub_10, exception_stub_11
+    .quad exception_stub_12, exception_stub_13, exception_stub_14, 0
+    .quad exception_stub_16, exception_stub_17, exception_stub_18, exception_stub_19
+    .quad exception_stub_20, 0, 0, 0
+    .quad 0, 0, 0, 0
+    .quad 0, exception_stub_29, exception_stub_30, 0
+.popsection
+"#
+);
+
+unsafe extern "C" {
+    /// Entry points of the stubs above, by vector. Reserved vectors are 0.
+    static exception_stubs: [u64; 32];
+}
+
+/// Names of the architectural exceptions, by vector.
+const EXCEPTION_NAMES: [(&str, &str); 32] = [
+    ("DIVIDE ERROR", "#DE"),
+    ("DEBUG", "#DB"),
+    ("NON-MASKABLE INTERRUPT", "NMI"),
+    ("BREAKPOINT", "#BP"),
+    ("OVERFLOW", "#OF"),
+    ("BOUND RANGE EXCEEDED", "#BR"),
+    ("INVALID OPCODE", "#UD"),
+    ("DEVICE NOT AVAILABLE", "#NM"),
+    ("DOUBLE FAULT", "#DF"),
+    ("COPROCESSOR SEGMENT OVERRUN", "-"),
+    ("INVALID TSS", "#TS"),
+    ("SEGMENT NOT PRESENT", "#NP"),
+    ("STACK SEGMENT FAULT", "#SS"),
+    ("GENERAL PROTECTION FAULT", "#GP"),
+    ("PAGE FAULT", "#PF"),
+    ("RESERVED", "-"),
+    ("X87 FLOATING POINT", "#MF"),
+    ("ALIGNMENT CHECK", "#AC"),
+    ("MACHINE CHECK", "#MC"),
+    ("SIMD FLOATING POINT", "#XM"),
+    ("VIRTUALIZATION", "#VE"),
+    ("CONTROL PROTECTION", "#CP"),
+    ("RESERVED", "-"),
+    ("RESERVED", "-"),
+    ("RESERVED", "-"),
+    ("RESERVED", "-"),
+    ("RESERVED", "-"),
+    ("RESERVED", "-"),
+    ("HYPERVISOR INJECTION", "#HV"),
+    ("VMM COMMUNICATION", "#VC"),
+    ("SECURITY", "#SX"),
+    ("RESERVED", "-"),
+];
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        let stub = |vector: usize| VirtAddr::new(unsafe { exception_stubs[vector] });
+        unsafe {
+            idt.divide_error.set_handler_addr(stub(0));
+            idt.debug.set_handler_addr(stub(1));
+            idt.non_maskable_interrupt
+                .set_handler_addr(stub(2))
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            idt.breakpoint.set_handler_addr(stub(3));
+            idt.overflow.set_handler_addr(stub(4));
+            idt.bound_range_exceeded.set_handler_addr(stub(5));
+            idt.invalid_opcode.set_handler_addr(stub(6));
+            idt.device_not_available.set_handler_addr(stub(7));
+            idt.double_fault
+                .set_handler_addr(stub(8))
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt.coprocessor_segment_overrun.set_handler_addr(stub(9));
+            idt.invalid_tss.set_handler_addr(stub(10));
+            idt.segment_not_present.set_handler_addr(stub(11));
+            idt.stack_segment_fault.set_handler_addr(stub(12));
+            idt.general_protection_fault.set_handler_addr(stub(13));
+            idt.page_fault.set_handler_addr(stub(14));
+            idt.x87_floating_point.set_handler_addr(stub(16));
+            idt.alignment_check.set_handler_addr(stub(17));
+            idt.machine_check
+                .set_handler_addr(stub(18))
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+            idt.simd_floating_point.set_handler_addr(stub(19));
+            idt.virtualization.set_handler_addr(stub(20));
+            idt.vmm_communication_exception.set_handler_addr(stub(29));
+            idt.security_exception.set_handler_addr(stub(30));
+        }
+        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        for (line, handler) in (irq::FIRST_LINE..irq::LINES).zip(LINE_HANDLERS) {
+            idt[(PIC_1_OFFSET + line) as usize].set_handler_fn(handler);
+        }
+        idt
+    };
+}
+
+pub fn init_idt() {
+    IDT.load();
+}
+
+/// Remaps the PICs, with every interrupt line masked. Interrupts stay disabled until
+/// `enable_irq` is first called.
+pub fn init_pics() {
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        write_masks(0xFF, 0xFF);
+    }
+}
+
+/// Unmasks the interrupt line, and enables interrupts.
+pub fn enable_irq(interrupt: InterruptIndex) {
+    set_line_masked(interrupt.irq(), false);
+    x86_64::instructions::interrupts::enable();
+}
+
+/// Masks or unmasks a PIC input line.
+pub fn set_line_masked(line: u8, masked: bool) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _pics = PICS.lock();
+        let (mut primary, mut secondary) = read_masks();
+        let (mask, bit) = match line {
+            0..8 => (&mut primary, line),
+            _ => {
+                // The secondary PIC is chained to line 2 of the primary
+                primary &= !(1 << 2);
+                (&mut secondary, line - 8)
+            }
+        };
+        match masked {
+            true => *mask |= 1 << bit,
+            false => *mask &= !(1 << bit),
+        }
+        unsafe { write_masks(primary, secondary) };
+    });
+}
+
+/// Halts the CPU until `ready` returns true, waking up for every interrupt to check.
+///
+/// `ready` is called with interrupts disabled, and `sti; hlt` only takes effect after `hlt`
+/// started, so an interrupt that makes `ready` true can't slip in between the check and the
+/// `hlt` and leave us sleeping.
+pub fn idle_until(mut ready: impl FnMut() -> bool) {
+    loop {
+        x86_64::instructions::interrupts::disable();
+        if ready() {
+            x86_64::instructions::interrupts::enable();
+            return;
+        }
+        x86_64::instructions::interrupts::enable_and_hlt();
+    }
+}
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xA0;
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_DATA: u16 = 0xA1;
+/// Makes the next read from a PIC's command port return its in-service register.
+const READ_ISR: u8 = 0x0B;
+
+fn read_masks() -> (u8, u8) {
+    unsafe { (Port::new(PIC_1_DATA).read(), Port::new(PIC_2_DATA).read()) }
+}
+
+unsafe fn write_masks(primary: u8, secondary: u8) {
+    unsafe {
+        Port::new(PIC_1_DATA).write(primary);
+        Port::new(PIC_2_DATA).write(secondary);
+    }
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    timer::handle_interrupt();
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Timer as u8);
+    }
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    keyboard::handle_interrupt();
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Keyboard as u8);
+    }
+}
+
+/// Whether the PIC is currently handling an interrupt on `line`.
+fn in_service(line: u8) -> bool {
+    let (command, bit) = match line {
+        0..8 => (PIC_1_COMMAND, line),
+        _ => (PIC_2_COMMAND, line - 8),
+    };
+    let mut port = Port::<u8>::new(command);
+    unsafe {
+        port.write(READ_ISR);
+        port.read() & (1 << bit) != 0
+    }
+}
+
+/// One handler per line that isn't used by the kernel, since an `x86-interrupt` handler can't
+/// tell which vector it was called for.
+const LINE_HANDLERS: [HandlerFunc; (irq::LINES - irq::FIRST_LINE) as usize] = {
+    macro_rules! line_handlers {
+        ($($line:literal)*) => {
+            [$({
+                extern "x86-interrupt" fn handler(_stack_frame: InterruptStackFrame) {
+                    line_interrupt($line);
+                }
+                handler
+            }),*]
+        };
+    }
+    line_handlers!(3 4 5 6 7 8 9 10 11 12 13 14 15)
+};
+
+fn line_interrupt(line: u8) {
+    // The PICs raise the lowest priority line for an interrupt that went away before it was
+    // acknowledged, which isn't in service then
+    if (line == 7 || line == 15) && !in_service(line) {
+        if line == 15 {
+            // The primary PIC did see something on the chained line
+            unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + 2) };
+        }
+        return;
+    }
+
+    irq::handle_interrupt(line);
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + line);
+    }
+}
+
+/// Called by `exception_common` for every exception.
+#[unsafe(no_mangle)]
+extern "C" fn exception_handler(frame: &mut ExceptionFrame, fxsave: &FxSaveArea) {
+    if recovery::redirect_fault(frame) {
+        return;
+    }
+
+    // Whoever held these is never going to release them
+    unsafe {
+        vga_buffer::WRITER.force_unlock();
+        serial::SERIAL.force_unlock();
+    }
+
+    report(frame, fxsave, |args| {
+        let _ = vga_buffer::WRITER.lock().write_fmt(args);
+        if let Some(port) = serial::SERIAL.lock().as_mut() {
+            let _ = port.write_fmt(args);
+        }
+    });
+
+    loop {
+        hlt();
+    }
+}
+
+/// Describes the exception and the registers at the time, line by line.
+fn report(
+    frame: &ExceptionFrame,
+    fxsave: &FxSaveArea,
+    mut print: impl FnMut(core::fmt::Arguments),
+) {
+    let (name, mnemonic) = EXCEPTION_NAMES[frame.vector as usize % 32];
+    let cr2 = Cr2::read();
+
+    print(format_args!(
+        "\nEXCEPTION: {name} ({mnemonic}, vector {}), error code {:#x}\n",
+        frame.vector, frame.error_code
+    ));
+    match frame.vector {
+        // Overflowing the stack faults on the guard page. Pushing the page fault's stack
+        // frame onto the same stack then faults again, which ends up as a double fault.
+        8 | 14 if stack::is_guard_page(cr2) => print(format_args!(
+            "KERNEL STACK OVERFLOW (hit the guard page at {cr2:?})\n"
+        )),
+        14 => {
+            let error = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+            print(format_args!("{error:?} at {cr2:?}\n"))
+        }
+        19 => {
+            // As it was at the fault: the live one may have changed since
+            let mxcsr = fxsave.mxcsr;
+            print(format_args!(
+                "MXCSR {mxcsr:#06x}, unmasked exceptions raised:"
+            ));
+            for (bit, name) in cpu::MXCSR_EXCEPTIONS.iter().enumerate() {
+                let raised = mxcsr & (1 << bit) != 0;
+                let masked = mxcsr & (1 << (bit + 7)) != 0;
+                if raised && !masked {
+                    print(format_args!(" {name}"));
+                }
+            }
+            print(format_args!("\n"));
+        }
+        _ => (),
+    }
+
+    print(format_args!(
+        "RIP {:#018x}  CS  {:#06x}  RFLAGS {:#010x}\n",
+        frame.rip, frame.cs, frame.rflags
+    ));
+    print(format_args!(
+        "RSP {:#018x}  SS  {:#06x}  CR2 {:#018x}\n",
+        frame.rsp,
+        frame.ss,
+        cr2.as_u64()
+    ));
+    let registers = [
+        ("RAX", frame.rax),
+        ("RBX", frame.rbx),
+        ("RCX", frame.rcx),
+        ("RDX", frame.rdx),
+        ("RSI", frame.rsi),
+        ("RDI", frame.rdi),
+        ("RBP", frame.rbp),
+        ("R8 ", frame.r8),
+        ("R9 ", frame.r9),
+        ("R10", frame.r10),
+        ("R11", frame.r11),
+        ("R12", frame.r12),
+        ("R13", frame.r13),
+        ("R14", frame.r14),
+        ("R15", frame.r15),
+    ];
+    for line in registers.chunks(3) {
+        for (name, value) in line {
+            print(format_args!("{name} {value:#018x}  "));
+        }
+        print(format_args!("\n"));
+    }
+}
@@ -0,0 +1,65 @@
+//! Interrupt lines handled by Python code.
+//!
+//! The interrupt handler only counts the interrupt and raises `SIGIRQ`, the Python callback runs
+//! at the next safe point in the interpreter. Until then the line stays masked: a level
+//! triggered device keeps its line asserted until the callback talks to it, and would otherwise
+//! interrupt us again the moment we return.
+
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+use crate::{interrupts, signals};
+
+/// Number of PIC input lines.
+pub const LINES: u8 = 16;
+/// Lines below this one are used by the kernel: the timer, the keyboard and the line the
+/// secondary PIC is chained to.
+pub const FIRST_LINE: u8 = 3;
+
+/// Number of interrupts received on each line.
+static COUNTS: [AtomicU64; LINES as usize] = [const { AtomicU64::new(0) }; LINES as usize];
+/// Lines whose callback hasn't run yet since their last interrupt, one bit each.
+static PENDING: AtomicU16 = AtomicU16::new(0);
+
+/// Called by the interrupt handler of `line`.
+pub fn handle_interrupt(line: u8) {
+    COUNTS[line as usize].fetch_add(1, Ordering::Relaxed);
+    interrupts::set_line_masked(line, true);
+    PENDING.fetch_or(1 << line, Ordering::Relaxed);
+    signals::raise(signals::SIGIRQ);
+}
+
+/// Starts receiving interrupts on `line`.
+pub fn claim(line: u8) {
+    interrupts::set_line_masked(line, false);
+}
+
+/// Stops receiving interrupts on `line`, and forgets about one that is pending.
+pub fn release(line: u8) {
+    interrupts::set_line_masked(line, true);
+    PENDING.fetch_and(!(1 << line), Ordering::Relaxed);
+}
+
+/// Releases every line a Python callback may have claimed.
+pub fn release_all() {
+    for line in FIRST_LINE..LINES {
+        release(line);
+    }
+}
+
+/// Takes the set of lines with an interrupt pending.
+pub fn take_pending() -> u16 {
+    PENDING.swap(0, Ordering::Relaxed)
+}
+
+/// Puts lines back that were taken but not handled, e.g. because a callback raised.
+pub fn requeue(lines: u16) {
+    if lines != 0 {
+        PENDING.fetch_or(lines, Ordering::Relaxed);
+        signals::raise(signals::SIGIRQ);
+    }
+}
+
+/// Number of interrupts received on `line` since boot.
+pub fn count(line: u8) -> u64 {
+    COUNTS[line as usize].load(Ordering::Relaxed)
+}
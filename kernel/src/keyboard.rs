@@ -0,0 +1,96 @@
+//! Bytes received from the PS/2 keyboard.
+//!
+//! The IRQ1 handler pushes every byte the keyboard sends into a ring buffer, which the line
+//! reader drains whenever it gets around to it. There is exactly one producer (the interrupt
+//! handler) and one consumer, so a pair of indices is all the synchronization needed, and
+//! keystrokes typed while Python code is running are kept until they are read.
+//!
+//! Ctrl+C can't wait for that, since the code that would read it may be stuck in a loop. The
+//! handler recognizes it in the scancode stream by itself and raises `SIGINT` instead of
+//! queueing the C, which makes the interpreter raise `KeyboardInterrupt`.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use x86_64::instructions::port::Port;
+
+use crate::signals;
+
+const DATA_PORT: u16 = 0x60;
+const CAPACITY: usize = 256;
+
+static BUFFER: [AtomicU8; CAPACITY] = [const { AtomicU8::new(0) }; CAPACITY];
+/// Index of the next byte to read. Only written by the consumer.
+static HEAD: AtomicUsize = AtomicUsize::new(0);
+/// Index of the next byte to write. Only written by the producer.
+static TAIL: AtomicUsize = AtomicUsize::new(0);
+
+// Scancode set 2
+const EXTENDED_PREFIX: u8 = 0xE0;
+const BREAK_PREFIX: u8 = 0xF0;
+/// Left Ctrl, or right Ctrl with the extended prefix.
+const CTRL: u8 = 0x14;
+const KEY_C: u8 = 0x21;
+
+// Only touched by the interrupt handler
+static EXTENDED: AtomicBool = AtomicBool::new(false);
+static BREAK: AtomicBool = AtomicBool::new(false);
+static LEFT_CTRL: AtomicBool = AtomicBool::new(false);
+static RIGHT_CTRL: AtomicBool = AtomicBool::new(false);
+
+/// Called by the IRQ1 handler.
+pub fn handle_interrupt() {
+    let byte = unsafe { Port::<u8>::new(DATA_PORT).read() };
+    if is_ctrl_c(byte) {
+        signals::raise(signals::SIGINT);
+    } else {
+        push(byte);
+    }
+}
+
+/// Follows the modifier state, and returns whether `byte` is C being pressed with Ctrl held.
+fn is_ctrl_c(byte: u8) -> bool {
+    match byte {
+        EXTENDED_PREFIX => EXTENDED.store(true, Ordering::Relaxed),
+        BREAK_PREFIX => BREAK.store(true, Ordering::Relaxed),
+        _ => {
+            let extended = EXTENDED.swap(false, Ordering::Relaxed);
+            let pressed = !BREAK.swap(false, Ordering::Relaxed);
+            match (byte, extended) {
+                (CTRL, false) => LEFT_CTRL.store(pressed, Ordering::Relaxed),
+                (CTRL, true) => RIGHT_CTRL.store(pressed, Ordering::Relaxed),
+                (KEY_C, false) => {
+                    let ctrl =
+                        LEFT_CTRL.load(Ordering::Relaxed) || RIGHT_CTRL.load(Ordering::Relaxed);
+                    return pressed && ctrl;
+                }
+                _ => (),
+            }
+        }
+    }
+    false
+}
+
+fn push(byte: u8) {
+    let tail = TAIL.load(Ordering::Relaxed);
+    if tail.wrapping_sub(HEAD.load(Ordering::Acquire)) == CAPACITY {
+        // Full. Nobody is going to read hundreds of keystrokes anyway
+        return;
+    }
+    BUFFER[tail % CAPACITY].store(byte, Ordering::Relaxed);
+    TAIL.store(tail.wrapping_add(1), Ordering::Release);
+}
+
+/// Takes the oldest byte out of the buffer.
+pub fn pop() -> Option<u8> {
+    let head = HEAD.load(Ordering::Relaxed);
+    if head == TAIL.load(Ordering::Acquire) {
+        return None;
+    }
+    let byte = BUFFER[head % CAPACITY].load(Ordering::Relaxed);
+    HEAD.store(head.wrapping_add(1), Ordering::Release);
+    Some(byte)
+}
+
+/// Whether there are bytes waiting to be read.
+pub fn has_input() -> bool {
+    HEAD.load(Ordering::Relaxed) != TAIL.load(Ordering::Acquire)
+}
@@ -0,0 +1,52 @@
+# The Python side of the `machine` module, see `install_machine` in main.rs.
+#
+# All timers share one kernel alarm, set for the earliest deadline. When it goes off, the
+# kernel raises a signal and `_dispatch` runs between two bytecode instructions. The alarm
+# counts timer ticks, so deadlines are rounded up to the next tick (10 ms).
+
+_timers = []
+
+
+class Timer:
+    """Calls callback(timer) every period_ms milliseconds, or just once if oneshot is set."""
+
+    def __init__(self, period_ms, callback, oneshot=False):
+        period_ms = int(period_ms)
+        if period_ms <= 0:
+            raise ValueError('period_ms must be positive')
+        self.period_ms = period_ms
+        self.callback = callback
+        self.oneshot = oneshot
+        self._deadline = _ticks_ms() + period_ms
+        _timers.append(self)
+        _schedule()
+
+    def deinit(self):
+        """Stops the timer."""
+        if self in _timers:
+            _timers.remove(self)
+            _schedule()
+
+    def __repr__(self):
+        mode = 'oneshot' if self.oneshot else 'periodic'
+        return f'Timer(period_ms={self.period_ms}, {mode})'
+
+
+def _schedule():
+    deadlines = [timer._deadline for timer in _timers]
+    _set_alarm(min(deadlines) if deadlines else None)
+
+
+def _dispatch(signum, frame):
+    now = _ticks_ms()
+    try:
+        for timer in [timer for timer in _timers if timer._deadline <= now]:
+            if timer.oneshot:
+                _timers.remove(timer)
+            else:
+                # Periods that were missed entirely are skipped rather than caught up on
+                missed = (now - timer._deadline) // timer.period_ms
+                timer._deadline += (missed + 1) * timer.period_ms
+            timer.callback(timer)
+    finally:
+        _schedule()
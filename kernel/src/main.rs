@@ -0,0 +1,1165 @@
+#![allow(static_mut_refs)]
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+extern crate alloc;
+
+use alloc::vec;
+use alloc::{borrow::ToOwned, rc::Rc, string::String, vec::Vec};
+use bootloader_api::config::{BootloaderConfig, Mapping};
+use bootloader_api::{entry_point, BootInfo};
+use boot::{BootModule, BootParams, BootStack, Console};
+use pc_keyboard::layouts::{
+    AnyLayout, Azerty, Colemak, De105Key, Dvorak104Key, Jis109Key, Uk105Key, Us104Key,
+};
+use pc_keyboard::ScancodeSet2;
+use rustpython_vm::convert::ToPyObject;
+use rustpython_vm::function::{OptionalArg, OptionalOption};
+use rustpython_vm::scope::Scope;
+use rustpython_vm::builtins::{PyBaseExceptionRef, PyListRef, PyTypeRef};
+use rustpython_vm::{PyObjectRef, PyResult, TryFromObject, VirtualMachine};
+use alloc::format;
+use allocator::tracking;
+use recovery::RawAccess;
+use vga_buffer::Color;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::model_specific::{Efer, Msr};
+use x86_64::structures::port::{PortRead, PortWrite};
+
+use core::arch::x86_64::{__cpuid_count, _rdtsc};
+use core::{alloc::Layout, cell::RefCell, fmt::Write, panic::PanicInfo};
+use ps2::{error::ControllerError, flags::ControllerConfigFlags, Controller};
+
+#[macro_use]
+pub mod vga_buffer;
+mod atomics;
+pub mod allocator;
+pub mod boot;
+mod cmdline;
+mod cpu;
+mod framebuffer;
+mod fw_cfg;
+pub mod gdt;
+pub mod interrupts;
+mod irq;
+mod keyboard;
+pub mod memory;
+mod multiboot;
+mod random;
+mod recovery;
+mod rtc;
+pub mod serial;
+mod signals;
+pub mod stack;
+mod timer;
+
+/// Like `println!`, but for boot messages, which the `quiet` boot option turns off.
+macro_rules! boot_println {
+    ($($arg:tt)*) => {
+        if !cmdline::flag("quiet") {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Called when an allocation can never succeed, even from the emergency pool. Rather than
+/// panicking, the REPL reports a MemoryError and restarts the interpreter: Python objects do not
+/// survive this.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    recovery::recover();
+    panic!("memory allocation of {} bytes failed", layout.size());
+}
+
+/// This function is called on panic.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let location = info.location().unwrap();
+    let msg = alloc::format!(
+        "Panicked: {}:{} {}",
+        location.file(),
+        location.line(),
+        info.message()
+    );
+    let _ = vga_buffer::WRITER.lock().write_str(&msg);
+
+    loop {}
+}
+
+/// Reads a line from the keyboard. Returns `None` if Ctrl+C was pressed instead. Signal
+/// handlers, e.g. of timers, keep running while waiting.
+fn read_string(
+    vm: &VirtualMachine,
+    keyboard: &mut pc_keyboard::Keyboard<AnyLayout, ScancodeSet2>,
+) -> Option<String> {
+    let mut string = String::new();
+
+    vga_buffer::WRITER.lock().update_cursor();
+
+    loop {
+        if signals::take(signals::SIGINT) {
+            println!();
+            return None;
+        }
+
+        // Run the handlers of whatever woke us up, like the interpreter does between statements
+        if let Err(e) = vm.check_signals() {
+            println!();
+            print_exception(vm, &e);
+            print!(">>> {string}");
+        }
+
+        while let Some(byte) = keyboard::pop() {
+            if let Ok(Some(event)) = keyboard.add_byte(byte) {
+                if let Some(key) = keyboard.process_keyevent(event.clone()) {
+                    let mut backspace = false;
+
+                    if let pc_keyboard::DecodedKey::Unicode(c) = key {
+                        match c {
+                            '\n' => {
+                                println!();
+                                return Some(string);
+                            }
+                            '\u{8}' => backspace = true,
+                            c if !c.is_control() => {
+                                print!("{c}");
+                                string.push(c);
+                            }
+                            _ => (),
+                        }
+                    }
+
+                    if let pc_keyboard::DecodedKey::RawKey(pc_keyboard::KeyCode::Backspace) = key {
+                        backspace = true;
+                    }
+
+                    if backspace {
+                        if let Some(_) = string.pop() {
+                            let mut lck = vga_buffer::WRITER.lock();
+                            lck.column_position = lck.column_position.checked_sub(1).unwrap_or(0);
+                            lck.write_byte(b' ');
+                            lck.column_position = lck.column_position.checked_sub(1).unwrap_or(0);
+                            lck.update_cursor();
+                        }
+                    }
+                }
+            }
+        }
+
+        // Sleep until the next keystroke or signal
+        interrupts::idle_until(|| {
+            keyboard::has_input()
+                || signals::pending(signals::SIGINT)
+                || signals::any_handled_pending(vm)
+        });
+    }
+}
+
+fn anon_object(vm: &VirtualMachine, name: &str) -> rustpython_vm::PyObjectRef {
+    let py_type = vm.builtins.get_attr("type", vm).unwrap();
+    let args = (name, vm.ctx.new_tuple(vec![]), vm.ctx.new_dict());
+    py_type.call(args, vm).unwrap()
+}
+
+fn install_stdout(vm: &VirtualMachine) {
+    let sys = vm.import("sys", 0).unwrap();
+
+    let stdout = anon_object(vm, "InternalStdout");
+
+    let writer = vm.new_function("write", move |s: String| print!("{s}"));
+
+    stdout.set_attr("write", writer, vm).unwrap();
+
+    sys.set_attr("stdout", stdout.clone(), vm).unwrap();
+}
+
+fn install_lowlevel(vm: &VirtualMachine, scope: Scope) {
+    /// Memory operations. Faults are raised as `MemoryAccessError`.
+    fn rw_dtype<T: ToPyObject + TryFromObject + RawAccess>(
+        vm: &VirtualMachine,
+        scope: Scope,
+        access_error: PyTypeRef,
+    ) {
+        let tyname = core::any::type_name::<T>();
+        let name = format!("read_{tyname}").leak();
+        let error = access_error.clone();
+        let read_byte = vm.new_function(
+            name,
+            move |address: u64, vm: &VirtualMachine| -> PyResult<T> {
+                recovery::catch_fault(|| unsafe { T::read(address) })
+                    .map_err(|fault| memory_access_error(vm, &error, "reading", address, fault))
+            },
+        );
+        scope
+            .globals
+            .set_item(name, read_byte.into(), vm)
+            .unwrap();
+
+        let name = format!("write_{tyname}").leak();
+        let write_byte = vm.new_function(
+            name,
+            move |address: u64, value: T, vm: &VirtualMachine| -> PyResult<()> {
+                recovery::catch_fault(|| unsafe { T::write(address, value) })
+                    .map_err(|fault| {
+                        memory_access_error(vm, &access_error, "writing", address, fault)
+                    })
+            },
+        );
+
+        scope
+            .globals
+            .set_item(name, write_byte.into(), vm)
+            .unwrap();
+    }
+
+    fn memory_access_error(
+        vm: &VirtualMachine,
+        error: &PyTypeRef,
+        access: &str,
+        address: u64,
+        fault: recovery::Fault,
+    ) -> PyBaseExceptionRef {
+        let mut message = format!(
+            "{} {access} {address:#x} (error code {:#x}",
+            fault.description(),
+            fault.error_code
+        );
+        // Differs from `address` when a wider access straddles into an unmapped page
+        if let Some(faulting) = fault.address {
+            message.push_str(&format!(", faulting address {faulting:#x}"));
+        }
+        message.push(')');
+        vm.new_exception_msg(error.clone(), message)
+    }
+
+    /// I/O operations
+    fn rx_dtype<T: ToPyObject + TryFromObject + PortRead + PortWrite>(vm: &VirtualMachine, scope: Scope) {
+        let tyname = core::any::type_name::<T>();
+        let name = format!("send_{tyname}").leak();
+        let send_byte = vm.new_function(name, move |port: u16, value: T| unsafe {
+            Port::new(port).write(value);
+        });
+
+        scope
+            .globals
+            .set_item(name, send_byte.into(), vm)
+            .unwrap();
+
+        let name = format!("recv_{tyname}").leak();
+        let recv_byte = vm.new_function(name, move |port: u16| -> T {
+            unsafe { Port::new(port).read() }
+        });
+
+        scope
+            .globals
+            .set_item(name, recv_byte.into(), vm)
+            .unwrap();
+
+    }
+
+    rx_dtype::<u8>(vm, scope.clone());
+    rx_dtype::<u16>(vm, scope.clone());
+    rx_dtype::<u32>(vm, scope.clone());
+
+    let access_error = vm.ctx.new_exception_type(
+        "builtins",
+        "MemoryAccessError",
+        Some(vec![vm.ctx.exceptions.os_error.to_owned()]),
+    );
+    scope
+        .globals
+        .set_item("MemoryAccessError", access_error.clone().into(), vm)
+        .unwrap();
+
+    rw_dtype::<u8>(vm, scope.clone(), access_error.clone());
+    rw_dtype::<u16>(vm, scope.clone(), access_error.clone());
+    rw_dtype::<u32>(vm, scope.clone(), access_error.clone());
+    rw_dtype::<u64>(vm, scope.clone(), access_error.clone());
+
+    rw_dtype::<i8>(vm, scope.clone(), access_error.clone());
+    rw_dtype::<i16>(vm, scope.clone(), access_error.clone());
+    rw_dtype::<i32>(vm, scope.clone(), access_error.clone());
+    rw_dtype::<i64>(vm, scope.clone(), access_error.clone());
+
+    // CPU state
+    let cpuid = vm.new_function(
+        "cpuid",
+        |leaf: u32, subleaf: OptionalArg<u32>, vm: &VirtualMachine| -> PyObjectRef {
+            let result = unsafe { __cpuid_count(leaf, subleaf.unwrap_or(0)) };
+            vm.new_tuple((result.eax, result.ebx, result.ecx, result.edx)).into()
+        },
+    );
+    scope
+        .globals
+        .set_item("cpuid", cpuid.into(), vm)
+        .unwrap();
+
+    let rdtsc = vm.new_function("rdtsc", || unsafe { _rdtsc() });
+    scope
+        .globals
+        .set_item("rdtsc", rdtsc.into(), vm)
+        .unwrap();
+
+    /// Faults accessing an MSR, usually #GP because it doesn't exist, are raised as `OSError`.
+    fn msr_error(
+        vm: &VirtualMachine,
+        access: &str,
+        msr: u32,
+        fault: recovery::Fault,
+    ) -> PyBaseExceptionRef {
+        vm.new_os_error(format!("{} {access} MSR {msr:#x}", fault.description()))
+    }
+
+    let rdmsr = vm.new_function("rdmsr", |msr: u32, vm: &VirtualMachine| -> PyResult<u64> {
+        recovery::catch_fault(|| unsafe { Msr::new(msr).read() })
+            .map_err(|fault| msr_error(vm, "reading", msr, fault))
+    });
+    scope
+        .globals
+        .set_item("rdmsr", rdmsr.into(), vm)
+        .unwrap();
+
+    let wrmsr = vm.new_function(
+        "wrmsr",
+        |msr: u32, value: u64, vm: &VirtualMachine| -> PyResult<()> {
+            recovery::catch_fault(|| unsafe { Msr::new(msr).write(value) })
+                .map_err(|fault| msr_error(vm, "writing", msr, fault))
+        },
+    );
+    scope
+        .globals
+        .set_item("wrmsr", wrmsr.into(), vm)
+        .unwrap();
+
+    let control_registers: [(&'static str, fn() -> u64); 5] = [
+        ("read_cr0", Cr0::read_raw),
+        ("read_cr2", || Cr2::read().as_u64()),
+        ("read_cr3", || {
+            let (frame, flags) = Cr3::read_raw();
+            frame.start_address().as_u64() | flags as u64
+        }),
+        ("read_cr4", Cr4::read_raw),
+        ("read_efer", Efer::read_raw),
+    ];
+    for (name, read) in control_registers {
+        let read = vm.new_function(name, read);
+        scope
+            .globals
+            .set_item(name, read.into(), vm)
+            .unwrap();
+    }
+
+    let alloc_bench = vm.new_function("alloc_bench", |ops: OptionalArg<usize>| {
+        allocator::bench::run(ops.unwrap_or(100_000))
+    });
+    scope
+        .globals
+        .set_item("alloc_bench", alloc_bench.into(), vm)
+        .unwrap();
+}
+
+/// `register_irq(line, callback)` and friends, for interrupt driven drivers written in Python.
+/// `callback(line)` is called at the next safe point after an interrupt on `line`, once no
+/// matter how many arrived in the meantime, and the line stays masked until it returns.
+fn install_irq(vm: &VirtualMachine, scope: Scope) {
+    fn check_line(vm: &VirtualMachine, line: u8) -> PyResult<()> {
+        match line {
+            irq::FIRST_LINE..irq::LINES => Ok(()),
+            0..irq::FIRST_LINE => {
+                Err(vm.new_value_error(format!("IRQ {line} is used by the kernel")))
+            }
+            _ => Err(vm.new_value_error(format!("IRQ {line} doesn't exist"))),
+        }
+    }
+
+    // The callback of each line, or None
+    let callbacks = vm.ctx.new_list(vec![vm.ctx.none(); irq::LINES as usize]);
+    let callback = |callbacks: &PyListRef, vm: &VirtualMachine, line: u8| {
+        let callback = callbacks.borrow_vec()[line as usize].clone();
+        (!vm.is_none(&callback)).then_some(callback)
+    };
+
+    let handlers = callbacks.clone();
+    let register_irq = vm.new_function(
+        "register_irq",
+        move |line: u8, callback: PyObjectRef, vm: &VirtualMachine| -> PyResult<()> {
+            check_line(vm, line)?;
+            if !callback.is_callable() {
+                return Err(vm.new_type_error("callback must be callable".to_owned()));
+            }
+            handlers.borrow_vec_mut()[line as usize] = callback;
+            irq::claim(line);
+            Ok(())
+        },
+    );
+    scope
+        .globals
+        .set_item("register_irq", register_irq.into(), vm)
+        .unwrap();
+
+    let handlers = callbacks.clone();
+    let unregister_irq = vm.new_function(
+        "unregister_irq",
+        move |line: u8, vm: &VirtualMachine| -> PyResult<()> {
+            check_line(vm, line)?;
+            if callback(&handlers, vm, line).is_none() {
+                return Err(vm.new_value_error(format!("IRQ {line} has no handler")));
+            }
+            irq::release(line);
+            handlers.borrow_vec_mut()[line as usize] = vm.ctx.none();
+            Ok(())
+        },
+    );
+    scope
+        .globals
+        .set_item("unregister_irq", unregister_irq.into(), vm)
+        .unwrap();
+
+    let irq_count = vm.new_function("irq_count", |line: u8, vm: &VirtualMachine| -> PyResult<u64> {
+        check_line(vm, line)?;
+        Ok(irq::count(line))
+    });
+    scope
+        .globals
+        .set_item("irq_count", irq_count.into(), vm)
+        .unwrap();
+
+    let dispatch = vm.new_function(
+        "dispatch_irqs",
+        move |_signum: PyObjectRef, _frame: PyObjectRef, vm: &VirtualMachine| -> PyResult<()> {
+            let mut pending = irq::take_pending();
+            while pending != 0 {
+                let line = pending.trailing_zeros() as u8;
+                pending &= !(1 << line);
+                // It may have been unregistered since
+                let Some(handler) = callback(&callbacks, vm, line) else {
+                    continue;
+                };
+                let result = handler.call((line,), vm);
+                // Unless the callback unregistered itself
+                if callback(&callbacks, vm, line).is_some() {
+                    irq::claim(line);
+                }
+                if let Err(error) = result {
+                    irq::requeue(pending);
+                    return Err(error);
+                }
+            }
+            Ok(())
+        },
+    );
+    signals::set_handler(vm, signals::SIGIRQ, dispatch.into());
+}
+
+/// Creates an empty module and registers it in `sys.modules`, so it can be imported.
+fn new_module(vm: &VirtualMachine, name: &'static str) -> PyObjectRef {
+    let module: PyObjectRef = vm.new_module(name, vm.ctx.new_dict(), None).into();
+    let modules = vm.sys_module.get_attr("modules", vm).unwrap();
+    modules.set_item(name, module.clone(), vm).unwrap();
+    module
+}
+
+fn install_signals(vm: &VirtualMachine) {
+    let out_of_memory = vm.new_function(
+        "out_of_memory",
+        |_signum: PyObjectRef, _frame: PyObjectRef, vm: &VirtualMachine| -> PyResult<()> {
+            allocator::take_out_of_memory();
+            Err(vm.new_memory_error("Out of memory".to_owned()))
+        },
+    );
+    signals::set_handler(vm, signals::SIGMEMORY, out_of_memory.into());
+
+    let keyboard_interrupt = vm.new_function(
+        "keyboard_interrupt",
+        |_signum: PyObjectRef, _frame: PyObjectRef, vm: &VirtualMachine| -> PyResult<()> {
+            let keyboard_interrupt = vm.ctx.exceptions.keyboard_interrupt.to_owned();
+            Err(vm.new_exception_empty(keyboard_interrupt))
+        },
+    );
+    signals::set_handler(vm, signals::SIGINT, keyboard_interrupt.into());
+}
+
+/// Sets the recursion limit so that Python code runs out of recursion before the kernel runs
+/// out of stack, by measuring how much stack one level of Python recursion actually takes.
+fn tune_recursion_limit(vm: &VirtualMachine) {
+    /// Stack kept free for whatever runs at the deepest level, e.g. the compiler or printing
+    const SAFETY_MARGIN: u64 = 64 * 1024;
+    const CALIBRATION_DEPTH: u64 = 16;
+
+    let scope = vm.new_scope_with_builtins();
+    let stack_pointer = vm.new_function("stack_pointer", stack::stack_pointer);
+    scope
+        .globals
+        .set_item("stack_pointer", stack_pointer.into(), vm)
+        .unwrap();
+
+    let source = format!(
+        "def depth(n):\n    return stack_pointer() if n == 0 else depth(n - 1)\n\
+         per_level = (depth(0) - depth({CALIBRATION_DEPTH})) // {CALIBRATION_DEPTH}\n"
+    );
+    vm.run_code_string(scope.clone(), &source, "<calibration>".to_owned())
+        .unwrap();
+    let per_level = scope.globals.get_item("per_level", vm).unwrap();
+    let per_level = u64::try_from_object(vm, per_level).unwrap().max(1);
+
+    let Some(bottom) = stack::bottom() else {
+        boot_println!("Running on an unknown stack, keeping the default recursion limit");
+        return;
+    };
+    let available = stack::stack_pointer() - bottom;
+    let limit = available.saturating_sub(SAFETY_MARGIN) / per_level;
+    vm.recursion_limit.set(limit as usize);
+    boot_println!("Recursion limit: {limit} ({per_level} bytes of stack per level)");
+}
+
+fn install_kernel(vm: &VirtualMachine, scope: Scope) {
+    let kernel = new_module(vm, "kernel");
+
+    let meminfo = vm.new_function("meminfo", |vm: &VirtualMachine| -> PyResult<PyObjectRef> {
+        let stats = allocator::ALLOCATOR.stats();
+        let free_frames = memory::with_frame_allocator(|frames| frames.free_frames());
+
+        let info = vm.ctx.new_dict();
+        for (key, value) in [
+            ("heap_size", stats.size),
+            ("heap_used", stats.used),
+            ("heap_free", stats.free),
+            ("largest_free_block", stats.largest_free_block),
+            ("slab_cached", stats.slab_cached),
+            ("allocations", stats.allocations),
+            ("deallocations", stats.deallocations),
+            ("live_allocations", stats.allocations - stats.deallocations),
+            ("live_bytes", stats.live_bytes),
+            ("physical_free", free_frames as usize * 4096),
+        ] {
+            info.set_item(key, value.to_pyobject(vm), vm)?;
+        }
+
+        let regions = memory::memory_map()
+            .iter()
+            .map(|region| {
+                vm.new_tuple((
+                    region.start,
+                    region.end,
+                    format!("{:?}", region.kind),
+                ))
+                .into()
+            })
+            .collect();
+        info.set_item("memory_map", vm.ctx.new_list(regions).into(), vm)?;
+
+        Ok(info.into())
+    });
+    kernel.set_attr("meminfo", meminfo, vm).unwrap();
+
+    let rsdp_address = vm.new_function("rsdp_address", || RSDP_ADDRESS.get().copied().flatten());
+    kernel.set_attr("rsdp_address", rsdp_address, vm).unwrap();
+
+    let boot_modules = vm.new_function("boot_modules", |vm: &VirtualMachine| -> PyObjectRef {
+        let modules = boot::modules()
+            .iter()
+            .map(|module| {
+                let data = vm.ctx.new_bytes(module.data.to_vec());
+                vm.new_tuple((module.name, data)).into()
+            })
+            .collect();
+        vm.ctx.new_list(modules).into()
+    });
+    kernel.set_attr("boot_modules", boot_modules, vm).unwrap();
+
+    let idle = vm.new_function("idle", || {
+        x86_64::instructions::interrupts::enable_and_hlt();
+    });
+    kernel.set_attr("idle", idle, vm).unwrap();
+
+    let cpu_features = vm.new_function("cpu_features", |vm: &VirtualMachine| -> PyObjectRef {
+        let features = cpu::features().map(|name| vm.ctx.new_str(name).into()).collect();
+        vm.ctx.new_list(features).into()
+    });
+    kernel.set_attr("cpu_features", cpu_features, vm).unwrap();
+
+    let uptime = vm.new_function("uptime", || timer::uptime_ns() as f64 / 1e9);
+    kernel.set_attr("uptime", uptime, vm).unwrap();
+
+    let leaktrack = vm.new_function("leaktrack", |enabled: bool| {
+        tracking::clear();
+        tracking::set_enabled(enabled);
+    });
+    kernel.set_attr("leaktrack", leaktrack, vm).unwrap();
+
+    let leaks = vm.new_function(
+        "leaks",
+        |count: OptionalArg<usize>, vm: &VirtualMachine| -> PyObjectRef {
+            const MAX_LEAKS: usize = 32;
+            let count = count.unwrap_or(10).min(MAX_LEAKS);
+
+            // Don't track the list we are about to build
+            let enabled = tracking::enabled();
+            tracking::set_enabled(false);
+            let sites = tracking::largest_sites::<MAX_LEAKS>();
+            let leaks = sites
+                .iter()
+                .take(count)
+                .filter(|total| total.count > 0)
+                .map(|total| {
+                    let addresses = total
+                        .site
+                        .iter()
+                        .filter(|&&address| address != 0)
+                        .map(|address| vm.ctx.new_str(format!("{address:#x}")).into())
+                        .collect();
+                    vm.new_tuple((total.bytes, total.count, vm.ctx.new_list(addresses)))
+                        .into()
+                })
+                .collect();
+            tracking::set_enabled(enabled);
+
+            vm.ctx.new_list(leaks).into()
+        },
+    );
+    kernel.set_attr("leaks", leaks, vm).unwrap();
+
+    scope.globals.set_item("kernel", kernel, vm).unwrap();
+}
+
+/// Fields of `time.struct_time`, in tuple order.
+const STRUCT_TIME_FIELDS: [&str; 9] = [
+    "tm_year", "tm_mon", "tm_mday", "tm_hour", "tm_min", "tm_sec", "tm_wday", "tm_yday",
+    "tm_isdst",
+];
+
+/// Defines `time.struct_time`, a tuple with the fields as named attributes.
+fn struct_time_type(vm: &VirtualMachine) -> PyObjectRef {
+    let scope = vm.new_scope_with_builtins();
+    let source = format!(
+        "class struct_time(tuple):\n\
+         \x20   def __repr__(self):\n\
+         \x20       fields = ', '.join(f'{{n}}={{v}}' for n, v in zip(FIELDS, self))\n\
+         \x20       return f'time.struct_time({{fields}})'\n\
+         FIELDS = {STRUCT_TIME_FIELDS:?}\n\
+         for i, name in enumerate(FIELDS):\n\
+         \x20   setattr(struct_time, name, property(lambda self, i=i: self[i]))\n"
+    );
+    vm.run_code_string(scope.clone(), &source, "<time>".to_owned())
+        .unwrap();
+    scope.globals.get_item("struct_time", vm).unwrap()
+}
+
+/// Breaks down a Unix time, or the current time, into a `time.struct_time`.
+fn struct_time(
+    vm: &VirtualMachine,
+    struct_time_type: &PyObjectRef,
+    seconds: Option<f64>,
+) -> PyResult {
+    let time = match seconds {
+        Some(seconds) if seconds.is_finite() => seconds.floor() as i64,
+        Some(_) => return Err(vm.new_value_error("Invalid value for timestamp".to_owned())),
+        None => rtc::unix_time_ns().div_euclid(1_000_000_000),
+    };
+    let date = rtc::DateTime::from_unix_time(time);
+    let fields = [
+        date.year,
+        date.month as i64,
+        date.day as i64,
+        date.hour as i64,
+        date.minute as i64,
+        date.second as i64,
+        date.weekday() as i64,
+        date.yearday() as i64,
+        0,
+    ]
+    .map(|field| field.to_pyobject(vm));
+    let fields = vm.ctx.new_tuple(fields.to_vec());
+    struct_time_type.call((fields,), vm)
+}
+
+/// A `time` module with the clocks we have so far. The monotonic clocks count from boot, the
+/// wall clock comes from the RTC. There are no time zones, local time is UTC.
+fn install_time(vm: &VirtualMachine) {
+    let time = new_module(vm, "time");
+
+    let time_seconds = vm.new_function("time", || rtc::unix_time_ns() as f64 / 1e9);
+    time.set_attr("time", time_seconds, vm).unwrap();
+    let time_ns = vm.new_function("time_ns", rtc::unix_time_ns);
+    time.set_attr("time_ns", time_ns, vm).unwrap();
+
+    let struct_time_type = struct_time_type(vm);
+    time.set_attr("struct_time", struct_time_type.clone(), vm)
+        .unwrap();
+    for name in ["gmtime", "localtime"] {
+        let struct_time_type = struct_time_type.clone();
+        let breakdown = vm.new_function(
+            name,
+            move |seconds: OptionalOption<f64>, vm: &VirtualMachine| -> PyResult {
+                struct_time(vm, &struct_time_type, seconds.flatten())
+            },
+        );
+        time.set_attr(name, breakdown, vm).unwrap();
+    }
+
+    for name in ["monotonic", "perf_counter"] {
+        let clock = vm.new_function(name, || timer::uptime_ns() as f64 / 1e9);
+        time.set_attr(name, clock, vm).unwrap();
+    }
+    for name in ["monotonic_ns", "perf_counter_ns"] {
+        time.set_attr(name, vm.new_function(name, timer::uptime_ns), vm).unwrap();
+    }
+
+    // Signal handlers, like those of timers, only run between bytecode instructions, so sleep
+    // in a Python loop that gets back to the interpreter whenever a handler is due
+    let sleep_until = vm.new_function("_sleep_until", |deadline: u64, vm: &VirtualMachine| {
+        timer::sleep_until(deadline, || signals::any_handled_pending(vm))
+    });
+    time.set_attr("_sleep_until", sleep_until, vm).unwrap();
+    run_in_module(
+        vm,
+        &time,
+        "time",
+        "def sleep(seconds):\n\
+         \x20   if not seconds >= 0:\n\
+         \x20       raise ValueError('sleep length must be non-negative')\n\
+         \x20   deadline = monotonic_ns() + int(seconds * 1e9)\n\
+         \x20   while not _sleep_until(deadline):\n\
+         \x20       pass\n",
+    );
+}
+
+/// A `machine` module like MicroPython's, so far with just `Timer`.
+fn install_machine(vm: &VirtualMachine) {
+    let machine = new_module(vm, "machine");
+
+    let ticks_ms = vm.new_function("_ticks_ms", || timer::ticks() * 1000 / timer::TICK_HZ);
+    machine.set_attr("_ticks_ms", ticks_ms, vm).unwrap();
+    let set_alarm = vm.new_function("_set_alarm", |ms: Option<u64>| {
+        // Round up, so the alarm never goes off before the deadline
+        timer::set_alarm(ms.map(|ms| (ms * timer::TICK_HZ).div_ceil(1000)));
+    });
+    machine.set_attr("_set_alarm", set_alarm, vm).unwrap();
+
+    run_in_module(vm, &machine, "machine", include_str!("machine.py"));
+
+    let dispatch = machine.get_attr("_dispatch", vm).unwrap();
+    signals::set_handler(vm, signals::SIGTIMER, dispatch);
+}
+
+/// `os.urandom()`, and a `random` module that uses it.
+fn install_random(vm: &VirtualMachine) {
+    let os = new_module(vm, "os");
+    let urandom = vm.new_function("urandom", |size: usize, vm: &VirtualMachine| {
+        let mut bytes = vec![0; size];
+        random::fill(&mut bytes);
+        vm.ctx.new_bytes(bytes)
+    });
+    os.set_attr("urandom", urandom, vm).unwrap();
+
+    let random = new_module(vm, "random");
+    run_in_module(vm, &random, "random", include_str!("random.py"));
+}
+
+/// Runs Python source in the namespace of `module`, for the parts that are easier to write in
+/// Python.
+fn run_in_module(vm: &VirtualMachine, module: &PyObjectRef, name: &str, source: &str) {
+    let globals = module.dict().expect("Modules have a __dict__");
+    let scope = Scope::with_builtins(None, globals, vm);
+    if let Err(e) = vm.run_code_string(scope, source, format!("<{name}>")) {
+        print_exception(vm, &e);
+        panic!("Failed to set up the {name} module");
+    }
+}
+
+/// Sets `sys.argv` to the name of the init script, followed by the command line arguments
+/// that aren't boot options.
+fn install_argv(vm: &VirtualMachine) {
+    let script = cmdline::option("init").unwrap_or("");
+    let argv = core::iter::once(script)
+        .chain(cmdline::args())
+        .map(|arg| vm.ctx.new_str(arg).into())
+        .collect();
+    vm.sys_module
+        .set_attr("argv", vm.ctx.new_list(argv), vm)
+        .unwrap();
+}
+
+/// Runs the boot module named by the `init` boot option, if any.
+fn run_init(vm: &VirtualMachine, scope: Scope) {
+    let Some(name) = cmdline::option("init") else {
+        return;
+    };
+    let module = boot::modules()
+        .iter()
+        .find(|module| module.name == name || module.name.rsplit('/').next() == Some(name));
+    let Some(module) = module else {
+        println!("init: there is no boot module called {name:?}");
+        return;
+    };
+    let Ok(source) = core::str::from_utf8(module.data) else {
+        println!("init: {name:?} is not UTF-8");
+        return;
+    };
+
+    if let Err(e) = vm.run_code_string(scope, source, name.to_owned()) {
+        print_exception(vm, &e);
+    }
+}
+
+fn print_exception(vm: &VirtualMachine, e: &PyBaseExceptionRef) {
+    let mut s = String::new();
+    vm.write_exception(&mut s, e).unwrap();
+    println!("Exception: {s}");
+}
+
+/// Applies the `fg` and `bg` boot options.
+fn set_colors() {
+    let color = |key| cmdline::option(key).map(|name| Color::from_name(name).ok_or(name));
+    match (color("fg").transpose(), color("bg").transpose()) {
+        (Ok(None), Ok(None)) => (),
+        (Ok(foreground), Ok(background)) => {
+            vga_buffer::WRITER.lock().set_colors(foreground, background)
+        }
+        (Err(name), _) | (_, Err(name)) => println!("Unknown color {name:?}"),
+    }
+}
+
+/// The interpreter stack size picked with the `stack` boot option.
+fn interpreter_stack_size() -> u64 {
+    let Some(value) = cmdline::option("stack") else {
+        return INTERPRETER_STACK_SIZE;
+    };
+    match parse_size(value) {
+        Some(size) if size >= MIN_INTERPRETER_STACK_SIZE => size,
+        _ => {
+            let default = INTERPRETER_STACK_SIZE >> 20;
+            println!("Invalid stack size {value:?}, using {default}M");
+            INTERPRETER_STACK_SIZE
+        }
+    }
+}
+
+/// Parses a number of bytes with an optional `K`, `M` or `G` suffix, e.g. `16M`.
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// The keyboard layout picked with the `keymap` boot option.
+fn keyboard_layout() -> AnyLayout {
+    match cmdline::option("keymap").unwrap_or("us") {
+        "us" => AnyLayout::Us104Key(Us104Key),
+        "uk" => AnyLayout::Uk105Key(Uk105Key),
+        "de" => AnyLayout::De105Key(De105Key),
+        "azerty" => AnyLayout::Azerty(Azerty),
+        "dvorak" => AnyLayout::Dvorak104Key(Dvorak104Key),
+        "colemak" => AnyLayout::Colemak(Colemak),
+        "jis" => AnyLayout::Jis109Key(Jis109Key),
+        other => {
+            println!("Unknown keymap {other:?}, using us");
+            AnyLayout::Us104Key(Us104Key)
+        }
+    }
+}
+
+/// Size of the stack the interpreter runs on, unless the `stack` boot option says otherwise.
+/// RustPython's compiler and VM are stack hungry.
+const INTERPRETER_STACK_SIZE: u64 = 8 * 1024 * 1024;
+/// Less than this doesn't even get the interpreter through its own setup.
+const MIN_INTERPRETER_STACK_SIZE: u64 = 1024 * 1024;
+
+/// Where the kernel stack and physical memory mapping should go, and how large the stack is.
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.kernel_stack = Mapping::FixedAddress(stack::KERNEL_STACK_ADDRESS);
+    config.mappings.physical_memory = Some(Mapping::FixedAddress(PHYSICAL_MEMORY_OFFSET));
+    config.kernel_stack_size = stack::KERNEL_STACK_PAGES * 4096;
+    config
+};
+
+/// The virtual address offset from which physical memory is mapped, as described in
+/// https://os.phil-opp.com/paging-implementation/#map-the-complete-physical-memory
+/// The Multiboot shim maps it at the same place.
+const PHYSICAL_MEMORY_OFFSET: u64 = 0xFFFF800000000000;
+
+/// Physical address of the ACPI root table pointer, if the firmware provided one.
+static RSDP_ADDRESS: spin::Once<Option<u64>> = spin::Once::new();
+
+entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
+
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    let BootInfo {
+        memory_regions,
+        framebuffer,
+        physical_memory_offset,
+        rsdp_addr,
+        ramdisk_addr,
+        ramdisk_len,
+        ..
+    } = boot_info;
+
+    static RAMDISK: spin::Once<BootModule> = spin::Once::new();
+    let modules = match ramdisk_addr.into_option() {
+        Some(address) => {
+            let len = *ramdisk_len as usize;
+            let data = unsafe { core::slice::from_raw_parts(address as *const u8, len) };
+            core::slice::from_ref(RAMDISK.call_once(|| BootModule { name: "ramdisk", data }))
+        }
+        None => &[],
+    };
+
+    start(BootParams {
+        memory_regions,
+        physical_memory_offset: physical_memory_offset
+            .into_option()
+            .expect("The bootloader did not map physical memory"),
+        console: framebuffer.as_mut().into_option().map(Console::Framebuffer),
+        rsdp_address: rsdp_addr.into_option(),
+        cmdline: None,
+        modules,
+        stack: BootStack::Bootloader,
+    })
+}
+
+/// Brings up the kernel and the interpreter, however we were booted.
+fn start(params: BootParams) -> ! {
+    cpu::init();
+    gdt::init();
+    match params.console {
+        Some(Console::Framebuffer(framebuffer)) => vga_buffer::init_framebuffer(framebuffer),
+        Some(Console::VgaText) => vga_buffer::init_text_mode(),
+        None => (),
+    }
+    cmdline::init(params.cmdline);
+    set_colors();
+    serial::init();
+    interrupts::init_idt();
+    interrupts::init_pics();
+    timer::init();
+    rtc::init();
+
+    // Initialize paging and the heap. Must be called before ANY allocations!
+    unsafe { memory::init(params.memory_regions, params.physical_memory_offset) };
+    allocator::init().expect("Failed to map the initial heap");
+    stack::init(&params.stack);
+    boot::init(params.modules);
+
+    boot_println!("Starting...");
+    let now = rtc::DateTime::from_unix_time(rtc::unix_time_ns() / 1_000_000_000);
+    boot_println!(
+        "Date: {}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        now.year, now.month, now.day, now.hour, now.minute, now.second
+    );
+    if cpu::avx_enabled() {
+        boot_println!("AVX enabled");
+    }
+    match timer::tsc_hz() {
+        Some(hz) => boot_println!("TSC runs at {} MHz", hz / 1_000_000),
+        None => boot_println!("TSC not usable, timing by {} Hz ticks", timer::TICK_HZ),
+    }
+    if !cmdline::get().is_empty() {
+        boot_println!("Command line: {}", cmdline::get());
+    }
+    let rsdp_address = *RSDP_ADDRESS.call_once(|| params.rsdp_address);
+    if let Some(rsdp_address) = rsdp_address {
+        boot_println!("ACPI RSDP at {rsdp_address:#x}");
+    }
+    for module in params.modules {
+        boot_println!("Boot module {:?}: {} KiB", module.name, module.data.len() >> 10);
+    }
+    let free_frames = memory::with_frame_allocator(|frames| frames.free_frames());
+    boot_println!("Heap can grow to use {} MiB of free memory", (free_frames * 4096) >> 20);
+
+    let stack = stack::Stack::allocate(interpreter_stack_size())
+        .expect("Failed to allocate the interpreter stack");
+    boot_println!("Interpreter stack: {} KiB", stack.size() >> 10);
+    stack.switch_to(run_interpreter)
+}
+
+/// Creates an interpreter with the kernel's modules installed, and the scope the REPL runs in.
+fn new_interpreter() -> (rustpython_vm::Interpreter, Scope) {
+    let interpreter = rustpython_vm::Interpreter::without_stdlib(Default::default());
+    let scope = interpreter.enter(|vm| {
+        let scope = vm.new_scope_with_builtins();
+        install_stdout(vm);
+        install_lowlevel(vm, scope.clone());
+        install_irq(vm, scope.clone());
+        install_kernel(vm, scope.clone());
+        install_signals(vm);
+        install_time(vm);
+        install_machine(vm);
+        install_random(vm);
+        install_argv(vm);
+        scope
+    });
+    (interpreter, scope)
+}
+
+fn run_interpreter() -> ! {
+    let (mut interpreter, mut scope) = new_interpreter();
+
+    // The PS/2 controller is set up by polling, so its replies mustn't end up in the handler
+    initialize_ps2().unwrap();
+    interrupts::enable_irq(interrupts::InterruptIndex::Keyboard);
+
+    let mut keyboard = pc_keyboard::Keyboard::new(
+        pc_keyboard::ScancodeSet2::new(),
+        keyboard_layout(),
+        pc_keyboard::HandleControl::MapLettersToUnicode,
+    );
+
+    interpreter.enter(|vm| {
+        tune_recursion_limit(vm);
+        run_init(vm, scope.clone());
+    });
+    let recursion_limit = interpreter.enter(|vm| vm.recursion_limit.get());
+
+    println!("RustPython v0.4.0");
+    print!(">>> ");
+    vga_buffer::enable_cursor();
+
+    loop {
+        let Some(source) = interpreter.enter(|vm| read_string(vm, &mut keyboard)) else {
+            println!("KeyboardInterrupt");
+            print!(">>> ");
+            continue;
+        };
+        let source = source.trim();
+
+        let outcome = interpreter.enter(|vm| {
+            let before = allocator::ALLOCATOR.counters();
+
+            let mut result = None;
+            let caught = recovery::catch(|| {
+                result = Some(
+                    vm.compile(
+                        &source,
+                        rustpython_vm::compiler::Mode::Single,
+                        "<embedded>".to_owned(),
+                    )
+                    .map_err(|err| vm.new_syntax_error(&err, Some(&source)))
+                    .and_then(|code_obj| vm.run_code_obj(code_obj, scope.clone())),
+                );
+            });
+            let result = match caught {
+                Ok(()) => result.unwrap(),
+                Err(recovered) => return Err(recovered),
+            };
+
+            // The emergency pool may have been used without the MemoryError being raised yet
+            let result = match allocator::take_out_of_memory() {
+                true => result.and_then(|_| Err(vm.new_memory_error("Out of memory".to_owned()))),
+                false => result,
+            };
+
+            match result {
+                Err(e) => print_exception(vm, &e),
+                Ok(v) => {
+                    println!("{v:?}");
+                }
+            }
+
+            if tracking::enabled() {
+                let after = allocator::ALLOCATOR.counters();
+                println!(
+                    "[leaktrack] {:+} bytes, {:+} objects retained",
+                    after.live_bytes as isize - before.live_bytes as isize,
+                    after.live_allocations() as isize - before.live_allocations() as isize,
+                );
+            }
+            Ok(())
+        });
+
+        if let Err(recovery::Recovered) = outcome {
+            // We may have jumped out of a section that had interrupts disabled
+            x86_64::instructions::interrupts::enable();
+            // Nothing is left to handle the old interpreter's IRQs and timers
+            irq::release_all();
+            timer::set_alarm(None);
+            allocator::take_out_of_memory();
+
+            // The statement was abandoned halfway, without releasing the locks and borrows it
+            // held, so nothing reachable from the interpreter can be trusted anymore. Start over
+            // with a fresh one. The old one is leaked: dropping it would run destructors, which
+            // could run into those same locks.
+            let (fresh_interpreter, fresh_scope) = new_interpreter();
+            core::mem::forget(core::mem::replace(&mut interpreter, fresh_interpreter));
+            core::mem::forget(core::mem::replace(&mut scope, fresh_scope));
+            interpreter.enter(|vm| vm.recursion_limit.set(recursion_limit));
+
+            println!("MemoryError: Out of memory");
+            println!("The interpreter was restarted, none of its objects survived");
+        }
+        print!(">>> ");
+    }
+}
+
+fn initialize_ps2() -> Result<Controller, ControllerError> {
+    let mut controller = unsafe { Controller::new() };
+
+    // Step 3: Disable devices
+    controller.disable_keyboard()?;
+    controller.disable_mouse()?;
+
+    // Step 4: Flush data buffer
+    let _ = controller.read_data();
+
+    // Step 5: Set config
+    let mut config = controller.read_config()?;
+    // Disable interrupts and scancode translation
+    config.set(
+        ControllerConfigFlags::ENABLE_KEYBOARD_INTERRUPT
+            | ControllerConfigFlags::ENABLE_MOUSE_INTERRUPT
+            | ControllerConfigFlags::ENABLE_TRANSLATE,
+        false,
+    );
+    controller.write_config(config)?;
+
+    // Step 6: Controller self-test
+    controller.test_controller()?;
+    // Write config again in case of controller reset
+    controller.write_config(config)?;
+
+    // Step 7: Determine if there are 2 devices
+    let has_mouse = if config.contains(ControllerConfigFlags::DISABLE_MOUSE) {
+        controller.enable_mouse()?;
+        config = controller.read_config()?;
+        // If mouse is working, this should now be unset
+        !config.contains(ControllerConfigFlags::DISABLE_MOUSE)
+    } else {
+        false
+    };
+    // Disable mouse. If there's no mouse, this is ignored
+    controller.disable_mouse()?;
+
+    // Step 8: Interface tests
+    let keyboard_works = controller.test_keyboard().is_ok();
+    let mouse_works = has_mouse && controller.test_mouse().is_ok();
+
+    // Step 9 - 10: Enable and reset devices
+    config = controller.read_config()?;
+    if keyboard_works {
+        controller.enable_keyboard()?;
+        config.set(ControllerConfigFlags::DISABLE_KEYBOARD, false);
+        config.set(ControllerConfigFlags::ENABLE_KEYBOARD_INTERRUPT, true);
+        controller.keyboard().reset_and_self_test().unwrap();
+    }
+    /*
+    if mouse_works {
+        controller.enable_mouse()?;
+        config.set(ControllerConfigFlags::DISABLE_MOUSE, false);
+        config.set(ControllerConfigFlags::ENABLE_MOUSE_INTERRUPT, true);
+        controller.mouse().reset_and_self_test().unwrap();
+        // This will start streaming events from the mouse
+        controller.mouse().enable_data_reporting().unwrap();
+    }
+    */
+
+    // Write last configuration to enable devices and interrupts
+    controller.write_config(config)?;
+
+    Ok(controller)
+}
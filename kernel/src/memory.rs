@@ -0,0 +1,274 @@
+//! Physical frame allocation and page table management.
+//!
+//! The bootloader maps all of physical memory at `physical_memory_offset`, which lets us reach
+//! page tables and free frames without any extra bookkeeping. Everything here is allocation
+//! free, so it can safely be called from inside the global allocator.
+
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use spin::{Mutex, Once};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, Translate, UnmapError};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Start of the virtual window used by `map_mmio`.
+const MMIO_START: u64 = 0x_5555_0000_0000;
+
+/// Usable memory regions beyond this many are ignored. UEFI memory maps can get long.
+const MAX_REGIONS: usize = 256;
+
+const FRAME_SIZE: u64 = 4096;
+
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+static NEXT_MMIO: Mutex<u64> = Mutex::new(MMIO_START);
+static MEMORY_MAP: Once<&'static MemoryRegions> = Once::new();
+
+/// Hands out the `Usable` frames of the boot memory map.
+///
+/// Regions are consumed front to back. Frames given back through `FrameDeallocator` are kept
+/// on an intrusive free list which is stored inside the free frames themselves.
+pub struct BootInfoFrameAllocator {
+    /// Remaining usable frames, as `start..end` frame numbers.
+    regions: [(u64, u64); MAX_REGIONS],
+    region_count: usize,
+    /// Number of frames that can still be allocated.
+    available: u64,
+    /// Physical address of the first frame on the free list, if any.
+    free_list: Option<PhysAddr>,
+    phys_offset: VirtAddr,
+}
+
+impl BootInfoFrameAllocator {
+    /// Create a frame allocator from the passed memory map.
+    ///
+    /// # Safety
+    /// The caller must guarantee that all frames marked as `Usable` in the memory map are really
+    /// unused, and that all of physical memory is mapped at `phys_offset`.
+    pub unsafe fn init(memory_map: &'static MemoryRegions, phys_offset: VirtAddr) -> Self {
+        let mut regions = [(0, 0); MAX_REGIONS];
+        let mut region_count = 0;
+        let frames = memory_map
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable)
+            .map(|region| (region.start.div_ceil(FRAME_SIZE), region.end / FRAME_SIZE))
+            // Usable memory between unaligned reservations may not hold a single whole frame
+            .filter(|(start, end)| start < end);
+        for range in frames.take(MAX_REGIONS) {
+            regions[region_count] = range;
+            region_count += 1;
+        }
+
+        let available = regions.iter().map(|(start, end)| end - start).sum();
+
+        Self {
+            regions,
+            region_count,
+            available,
+            free_list: None,
+            phys_offset,
+        }
+    }
+
+    /// Number of frames that can still be allocated.
+    pub fn free_frames(&self) -> u64 {
+        self.available
+    }
+
+    unsafe fn read_link(&self, frame: PhysAddr) -> Option<PhysAddr> {
+        let link = unsafe { *(self.phys_offset + frame.as_u64()).as_ptr::<u64>() };
+        (link != u64::MAX).then(|| PhysAddr::new(link))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(addr) = self.free_list {
+            self.free_list = unsafe { self.read_link(addr) };
+            self.available -= 1;
+            return Some(PhysFrame::containing_address(addr));
+        }
+
+        let (start, _) = self.regions[..self.region_count]
+            .iter_mut()
+            .find(|(start, end)| start < end)?;
+        let frame = PhysFrame::containing_address(PhysAddr::new(*start * FRAME_SIZE));
+        *start += 1;
+        self.available -= 1;
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let link = self.free_list.map(|addr| addr.as_u64()).unwrap_or(u64::MAX);
+        unsafe {
+            *(self.phys_offset + frame.start_address().as_u64()).as_mut_ptr::<u64>() = link;
+        }
+        self.free_list = Some(frame.start_address());
+        self.available += 1;
+    }
+}
+
+/// Returns a mutable reference to the active level 4 table.
+///
+/// # Safety
+/// All of physical memory must be mapped at `phys_offset`, and this must only be called once
+/// to avoid aliasing `&mut` references.
+unsafe fn active_level_4_table(phys_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+    let virt = phys_offset + level_4_table_frame.start_address().as_u64();
+    unsafe { &mut *virt.as_mut_ptr() }
+}
+
+/// Sets up the frame allocator and page table mapper. Must be called before the heap exists.
+///
+/// # Safety
+/// All of physical memory must be mapped at `physical_memory_offset`, and the `Usable` regions
+/// of `memory_map` must really be unused.
+pub unsafe fn init(memory_map: &'static MemoryRegions, physical_memory_offset: u64) {
+    let phys_offset = VirtAddr::new(physical_memory_offset);
+    MEMORY_MAP.call_once(|| memory_map);
+    unsafe {
+        let level_4_table = active_level_4_table(phys_offset);
+        *MAPPER.lock() = Some(OffsetPageTable::new(level_4_table, phys_offset));
+        *FRAME_ALLOCATOR.lock() = Some(BootInfoFrameAllocator::init(memory_map, phys_offset));
+    }
+}
+
+/// Runs `f` with the mapper and frame allocator. The mapper lock is always taken first.
+fn with_paging<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BootInfoFrameAllocator) -> R,
+) -> R {
+    let mut mapper = MAPPER.lock();
+    let mut frames = FRAME_ALLOCATOR.lock();
+    f(
+        mapper.as_mut().expect("memory::init has not been called"),
+        frames.as_mut().expect("memory::init has not been called"),
+    )
+}
+
+/// Runs `f` with the frame allocator.
+pub fn with_frame_allocator<R>(f: impl FnOnce(&mut BootInfoFrameAllocator) -> R) -> R {
+    f(FRAME_ALLOCATOR
+        .lock()
+        .as_mut()
+        .expect("memory::init has not been called"))
+}
+
+/// The physical memory map reported by the bootloader.
+pub fn memory_map() -> &'static MemoryRegions {
+    MEMORY_MAP.get().expect("memory::init has not been called")
+}
+
+/// Virtual address at which physical address zero is mapped.
+pub fn physical_memory_offset() -> VirtAddr {
+    MAPPER
+        .lock()
+        .as_ref()
+        .expect("memory::init has not been called")
+        .phys_offset()
+}
+
+/// Maps `page` to a freshly allocated frame.
+pub fn map_page(page: Page, flags: PageTableFlags) -> Result<PhysFrame, MapToError<Size4KiB>> {
+    with_paging(|mapper, frames| {
+        let frame = frames
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        match unsafe { mapper.map_to(page, frame, flags, frames) } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(frame)
+            }
+            Err(e) => {
+                unsafe { frames.deallocate_frame(frame) };
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Maps `page` to the given physical `frame`.
+///
+/// # Safety
+/// The caller must make sure that mapping the frame does not create aliasing references to
+/// memory that is in use elsewhere.
+pub unsafe fn map_to(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    with_paging(|mapper, frames| {
+        unsafe { mapper.map_to(page, frame, flags, frames) }.map(|flush| flush.flush())
+    })
+}
+
+/// Unmaps `page` and returns the frame it pointed to. The frame is not freed.
+pub fn unmap_page(page: Page) -> Result<PhysFrame, UnmapError> {
+    with_paging(|mapper, _| {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        Ok(frame)
+    })
+}
+
+/// Unmaps `page` and gives its frame back to the frame allocator.
+///
+/// # Safety
+/// The frame must not be mapped anywhere else.
+pub unsafe fn free_page(page: Page) -> Result<(), UnmapError> {
+    with_paging(|mapper, frames| {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        unsafe { frames.deallocate_frame(frame) };
+        Ok(())
+    })
+}
+
+/// Replaces the flags of an already mapped page.
+///
+/// # Safety
+/// Changing the protection of memory that is in use can violate memory safety.
+pub unsafe fn protect(page: Page, flags: PageTableFlags) -> Result<(), FlagUpdateError> {
+    with_paging(|mapper, _| unsafe { mapper.update_flags(page, flags) }.map(|flush| flush.flush()))
+}
+
+/// Translates a virtual address to the physical address it is mapped to, if any.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    MAPPER
+        .lock()
+        .as_ref()
+        .expect("memory::init has not been called")
+        .translate_addr(addr)
+}
+
+/// Maps `size` bytes of device memory starting at `phys` into the MMIO window, uncached.
+///
+/// Returns the virtual address corresponding to `phys`.
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + size.max(1) - 1u64);
+    let frame_count = last - first + 1;
+
+    let base = {
+        let mut next = NEXT_MMIO.lock();
+        let base = *next;
+        *next += frame_count * FRAME_SIZE;
+        base
+    };
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH;
+    for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+        let page = Page::containing_address(VirtAddr::new(base + i as u64 * FRAME_SIZE));
+        unsafe { map_to(page, frame, flags)? };
+    }
+
+    Ok(VirtAddr::new(base) + (phys.as_u64() - first.start_address().as_u64()))
+}
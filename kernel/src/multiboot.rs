@@ -0,0 +1,461 @@
+//! Booting through Multiboot, e.g. from GRUB (Multiboot 2) or `qemu -kernel` (Multiboot 1).
+//!
+//! Both protocols enter the kernel in 32-bit protected mode without paging. The shim below
+//! builds just enough page tables to reach the higher half: the first `MAPPED_GIB` GiB of
+//! physical memory are identity mapped and mapped at `PHYSICAL_MEMORY_OFFSET`, like the
+//! bootloader does, and the first GiB is mapped at -2 GiB where the kernel is linked (see
+//! `linker.ld`). `multiboot_main` removes the identity mapping again, then translates the
+//! loader's information into `BootParams`.
+
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind, MemoryRegions};
+use core::ffi::CStr;
+
+use crate::PHYSICAL_MEMORY_OFFSET;
+use crate::boot::{BootModule, BootParams, BootStack, Console};
+
+/// Physical memory the shim maps. Memory above this is left out of the memory map.
+const MAPPED_GIB: u64 = 64;
+const BOOT_STACK_SIZE: usize = 64 * 1024;
+
+const MULTIBOOT1_MAGIC: u32 = 0x2BAD_B002;
+const MULTIBOOT2_MAGIC: u32 = 0x36D7_6289;
+
+const MAX_REGIONS: usize = 256;
+const MAX_MODULES: usize = 16;
+const MAX_RESERVED: usize = 64;
+
+core::arch::global_asm!(
+    r#"
+.pushsection .multiboot, "a"
+
+/* Multiboot 1, for `qemu -kernel`. The a.out kludge (flag bit 16) gives the load addresses
+   explicitly, since QEMU refuses to load 64-bit ELF files itself. */
+.align 4
+multiboot1_header:
+    .long 0x1BADB002
+    .long 0x00010003
+    .long 0x100000000 - (0x1BADB002 + 0x00010003)
+    .long multiboot1_header
+    .long __kernel_start_phys
+    .long __kernel_load_end_phys
+    .long __kernel_end_phys
+    .long multiboot_entry
+
+/* Multiboot 2, for GRUB */
+.align 8
+multiboot2_header:
+    .long 0xE85250D6
+    .long 0
+    .long multiboot2_header_end - multiboot2_header
+    .long 0x100000000 - (0xE85250D6 + (multiboot2_header_end - multiboot2_header))
+    /* Entry address: start in the shim rather than at the ELF entry point */
+    .align 8
+    .short 3, 0
+    .long 12
+    .long multiboot_entry
+    /* Console flags: we can print to the EGA text buffer */
+    .align 8
+    .short 4, 0
+    .long 12
+    .long 2
+    /* End */
+    .align 8
+    .short 0, 0
+    .long 8
+multiboot2_header_end:
+
+.popsection
+
+.pushsection .boot32, "ax"
+.code32
+.global multiboot_entry
+multiboot_entry:
+    cli
+    cld
+    mov edi, eax
+    mov esi, ebx
+
+    /* Page directories made of 2 MiB pages, covering all of the mapped physical memory */
+    xor ecx, ecx
+2:
+    mov eax, ecx
+    shl eax, 21
+    or eax, 0x83
+    mov dword ptr [multiboot_pd_phys + ecx * 8], eax
+    mov eax, ecx
+    shr eax, 11
+    mov dword ptr [multiboot_pd_phys + ecx * 8 + 4], eax
+    inc ecx
+    cmp ecx, {gib} * 512
+    jne 2b
+
+    /* One table pointing at all of them, for the identity and physical memory mappings */
+    xor ecx, ecx
+3:
+    mov eax, ecx
+    shl eax, 12
+    add eax, offset multiboot_pd_phys
+    or eax, 0x3
+    mov dword ptr [multiboot_pdpt_phys + ecx * 8], eax
+    inc ecx
+    cmp ecx, {gib}
+    jne 3b
+
+    /* And one mapping the first GiB at -2 GiB, where the kernel is linked */
+    mov eax, offset multiboot_pd_phys
+    or eax, 0x3
+    mov dword ptr [multiboot_pdpt_kernel_phys + 510 * 8], eax
+
+    mov eax, offset multiboot_pdpt_phys
+    or eax, 0x3
+    mov dword ptr [multiboot_pml4_phys], eax
+    mov dword ptr [multiboot_pml4_phys + 256 * 8], eax
+    mov eax, offset multiboot_pdpt_kernel_phys
+    or eax, 0x3
+    mov dword ptr [multiboot_pml4_phys + 511 * 8], eax
+
+    /* PAE, then long mode, then paging */
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+    mov eax, offset multiboot_pml4_phys
+    mov cr3, eax
+    mov ecx, 0xC0000080
+    rdmsr
+    or eax, 1 << 8
+    wrmsr
+    mov eax, cr0
+    or eax, 1 << 31
+    mov cr0, eax
+
+    lgdt [multiboot_gdt_pointer]
+    push 0x08
+    push offset multiboot_entry64
+    retf
+
+.code64
+multiboot_entry64:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    mov fs, ax
+    mov gs, ax
+    movabs rsp, offset multiboot_stack_top
+
+    /* Rust code may use SSE anywhere, so it has to be enabled before calling into it */
+    mov rax, cr0
+    and rax, ~(1 << 2)
+    or rax, 1 << 1
+    mov cr0, rax
+    mov rax, cr4
+    or rax, 3 << 9
+    mov cr4, rax
+
+    /* The upper halves of registers are undefined after entering long mode */
+    mov edi, edi
+    mov esi, esi
+    movabs rax, offset multiboot_main
+    call rax
+4:
+    hlt
+    jmp 4b
+
+.align 8
+multiboot_gdt:
+    .quad 0
+    .quad 0x00209A0000000000
+    .quad 0x0000920000000000
+multiboot_gdt_pointer:
+    .short multiboot_gdt_pointer - multiboot_gdt - 1
+    .long multiboot_gdt
+
+.popsection
+
+.pushsection .bss.multiboot, "aw", @nobits
+.align 4096
+.global multiboot_pml4, multiboot_pdpt, multiboot_pdpt_kernel, multiboot_pd
+multiboot_pml4:
+    .skip 4096
+multiboot_pdpt:
+    .skip 4096
+multiboot_pdpt_kernel:
+    .skip 4096
+multiboot_pd:
+    .skip 4096 * {gib}
+.global multiboot_stack, multiboot_stack_top
+multiboot_stack:
+    .skip {stack_size}
+multiboot_stack_top:
+.popsection
+"#,
+    gib = const MAPPED_GIB,
+    stack_size = const BOOT_STACK_SIZE,
+);
+
+unsafe extern "C" {
+    static mut multiboot_pml4: [u64; 512];
+    static __kernel_start_phys: u8;
+    static __kernel_end_phys: u8;
+    static multiboot_stack: u8;
+    static multiboot_stack_top: u8;
+}
+
+static mut REGIONS: [MemoryRegion; MAX_REGIONS] = [MemoryRegion::empty(); MAX_REGIONS];
+static mut REGION_COUNT: usize = 0;
+static mut MEMORY_REGIONS: Option<MemoryRegions> = None;
+static mut MODULES: [BootModule; MAX_MODULES] = [BootModule {
+    name: "",
+    data: &[],
+}; MAX_MODULES];
+static mut MODULE_COUNT: usize = 0;
+/// Physical ranges in use by the kernel or the loader's data, see `reserve`.
+static mut RESERVED: [(u64, u64); MAX_RESERVED] = [(0, 0); MAX_RESERVED];
+static mut RESERVED_COUNT: usize = 0;
+
+/// Called by the shim, with the loader's magic value and the physical address of its
+/// information structure.
+#[unsafe(no_mangle)]
+extern "C" fn multiboot_main(magic: u32, info: u32) -> ! {
+    unmap_identity();
+    // Multiboot loaders leave the display in text mode
+    crate::vga_buffer::init_text_mode();
+
+    let info = info as u64;
+    let LoaderInfo {
+        rsdp_address,
+        cmdline,
+    } = match magic {
+        MULTIBOOT2_MAGIC => unsafe { parse_multiboot2(info) },
+        MULTIBOOT1_MAGIC => unsafe { parse_multiboot1(info) },
+        _ => panic!("Not started by a Multiboot loader (magic {magic:#x})"),
+    };
+    let rsdp_address = rsdp_address.or_else(find_rsdp);
+
+    let kernel_start = &raw const __kernel_start_phys as u64;
+    let kernel_end = &raw const __kernel_end_phys as u64;
+    // Leave the BIOS data, and whatever the loader put below 1 MiB, alone
+    reserve(0, 0x10_0000);
+    reserve(kernel_start, kernel_end);
+
+    let (memory_regions, modules) = unsafe {
+        for &(start, end) in &RESERVED[..RESERVED_COUNT] {
+            take_out(start, end);
+        }
+        REGIONS[..REGION_COUNT].sort_unstable_by_key(|region| region.start);
+        let regions = MemoryRegions::from(&mut REGIONS[..REGION_COUNT]);
+        (&*MEMORY_REGIONS.insert(regions), &MODULES[..MODULE_COUNT])
+    };
+    crate::start(BootParams {
+        memory_regions,
+        physical_memory_offset: PHYSICAL_MEMORY_OFFSET,
+        console: Some(Console::VgaText),
+        rsdp_address,
+        cmdline,
+        modules,
+        stack: BootStack::Static {
+            bottom: &raw const multiboot_stack as u64,
+            top: &raw const multiboot_stack_top as u64,
+        },
+    })
+}
+
+/// Removes the identity mapping the shim needed to turn on paging, so that null pointers and
+/// other low addresses fault like they do when started by the bootloader. The shim's GDT is in
+/// low memory too, but nothing reads it again before `gdt::init` loads the kernel's own.
+fn unmap_identity() {
+    unsafe { (&raw mut multiboot_pml4).cast::<u64>().write_volatile(0) };
+    x86_64::instructions::tlb::flush_all();
+}
+
+/// What else the boot information contained, besides the memory map and modules.
+#[derive(Default)]
+struct LoaderInfo {
+    rsdp_address: Option<u64>,
+    cmdline: Option<&'static str>,
+}
+
+/// Reads the Multiboot 2 boot information.
+unsafe fn parse_multiboot2(info: u64) -> LoaderInfo {
+    let total_size = unsafe { read::<u32>(info) } as u64;
+    reserve(info, info + total_size);
+
+    let mut loader_info = LoaderInfo::default();
+    let mut tag = info + 8;
+    while tag < info + total_size {
+        let (kind, size) = unsafe { (read::<u32>(tag), read::<u32>(tag + 4) as u64) };
+        match kind {
+            0 => break,
+            // Command line
+            1 => loader_info.cmdline = Some(unsafe { c_str(tag + 8) }),
+            // Module
+            3 => unsafe {
+                let (start, end) = (read::<u32>(tag + 8) as u64, read::<u32>(tag + 12) as u64);
+                add_module(start, end, c_str(tag + 16));
+            },
+            // Memory map
+            6 => unsafe {
+                let entry_size = read::<u32>(tag + 8) as u64;
+                let mut entry = tag + 16;
+                while entry + entry_size <= tag + size {
+                    let (base, len) = (read::<u64>(entry), read::<u64>(entry + 8));
+                    add_region(base, base + len, read::<u32>(entry + 16));
+                    entry += entry_size;
+                }
+            },
+            // A copy of the ACPI 1.0 or 2.0 RSDP
+            14 | 15 => loader_info.rsdp_address = Some(tag + 8),
+            _ => (),
+        }
+        tag += size.next_multiple_of(8);
+    }
+    loader_info
+}
+
+/// Reads the Multiboot 1 boot information. It never includes the ACPI RSDP address.
+unsafe fn parse_multiboot1(info: u64) -> LoaderInfo {
+    const INFO_SIZE: u64 = 120;
+    const FLAG_CMDLINE: u32 = 1 << 2;
+    const FLAG_MODULES: u32 = 1 << 3;
+    const FLAG_MEMORY_MAP: u32 = 1 << 6;
+
+    reserve(info, info + INFO_SIZE);
+    let flags = unsafe { read::<u32>(info) };
+    let mut loader_info = LoaderInfo::default();
+
+    if flags & FLAG_CMDLINE != 0 {
+        let address = unsafe { read::<u32>(info + 16) } as u64;
+        let cmdline = unsafe { c_str(address) };
+        reserve(address, address + cmdline.len() as u64 + 1);
+        // Multiboot 1 loaders start the command line with the kernel's file name
+        let cmdline = cmdline.trim_start();
+        let args = cmdline
+            .find(char::is_whitespace)
+            .map_or("", |i| &cmdline[i..]);
+        loader_info.cmdline = Some(args);
+    }
+
+    if flags & FLAG_MODULES != 0 {
+        let (count, modules) = unsafe { (read::<u32>(info + 20), read::<u32>(info + 24) as u64) };
+        reserve(modules, modules + count as u64 * 16);
+        for i in 0..count as u64 {
+            let module = modules + i * 16;
+            unsafe {
+                let (start, end) = (read::<u32>(module) as u64, read::<u32>(module + 4) as u64);
+                let name_address = read::<u32>(module + 8) as u64;
+                let name = c_str(name_address);
+                reserve(name_address, name_address + name.len() as u64 + 1);
+                add_module(start, end, name);
+            }
+        }
+    }
+
+    if flags & FLAG_MEMORY_MAP != 0 {
+        let (len, map) = unsafe { (read::<u32>(info + 44) as u64, read::<u32>(info + 48) as u64) };
+        reserve(map, map + len);
+        // Each entry is preceded by its size, which doesn't count itself
+        let mut entry = map;
+        while entry < map + len {
+            unsafe {
+                let size = read::<u32>(entry) as u64;
+                let (base, length) = (read::<u64>(entry + 4), read::<u64>(entry + 12));
+                add_region(base, base + length, read::<u32>(entry + 20));
+                entry += size + 4;
+            }
+        }
+    }
+    loader_info
+}
+
+/// Searches the BIOS area for the ACPI RSDP, for loaders that don't pass it along.
+fn find_rsdp() -> Option<u64> {
+    (0xE_0000..0x10_0000)
+        .step_by(16)
+        .find(|&address| unsafe { read::<[u8; 8]>(address) } == *b"RSD PTR ")
+}
+
+/// Adds an entry of the loader's memory map. Type 1 is usable RAM in both protocols.
+fn add_region(start: u64, end: u64, kind: u32) {
+    let (kind, end) = match kind {
+        1 => (MemoryRegionKind::Usable, end.min(MAPPED_GIB << 30)),
+        kind => (MemoryRegionKind::UnknownBios(kind), end),
+    };
+    if start < end {
+        push_region(MemoryRegion { start, end, kind });
+    }
+}
+
+fn push_region(region: MemoryRegion) {
+    unsafe {
+        if REGION_COUNT < MAX_REGIONS {
+            REGIONS[REGION_COUNT] = region;
+            REGION_COUNT += 1;
+        }
+    }
+}
+
+/// Keeps the frame allocator away from `start..end`. Applied once the memory map is complete,
+/// since Multiboot 2 doesn't say in which order the tags come.
+fn reserve(start: u64, end: u64) {
+    unsafe {
+        assert!(RESERVED_COUNT < MAX_RESERVED, "Too many reserved ranges");
+        RESERVED[RESERVED_COUNT] = (start, end);
+        RESERVED_COUNT += 1;
+    }
+}
+
+/// Takes `start..end` out of the usable memory.
+fn take_out(start: u64, end: u64) {
+    for i in 0..unsafe { REGION_COUNT } {
+        let region = unsafe { REGIONS[i] };
+        if region.kind != MemoryRegionKind::Usable || region.end <= start || end <= region.start {
+            continue;
+        }
+
+        let used = MemoryRegion {
+            start: region.start.max(start),
+            end: region.end.min(end),
+            kind: MemoryRegionKind::Bootloader,
+        };
+        unsafe { REGIONS[i] = used };
+        if region.start < used.start {
+            push_region(MemoryRegion {
+                end: used.start,
+                ..region
+            });
+        }
+        if used.end < region.end {
+            push_region(MemoryRegion {
+                start: used.end,
+                ..region
+            });
+        }
+    }
+}
+
+fn add_module(start: u64, end: u64, name: &'static str) {
+    reserve(start, end);
+    let data = unsafe {
+        core::slice::from_raw_parts(
+            (PHYSICAL_MEMORY_OFFSET + start) as *const u8,
+            end.saturating_sub(start) as usize,
+        )
+    };
+    unsafe {
+        if MODULE_COUNT < MAX_MODULES {
+            MODULES[MODULE_COUNT] = BootModule { name, data };
+            MODULE_COUNT += 1;
+        }
+    }
+}
+
+/// Reads a value at a physical address.
+unsafe fn read<T: Copy>(address: u64) -> T {
+    unsafe { ((PHYSICAL_MEMORY_OFFSET + address) as *const T).read_unaligned() }
+}
+
+/// The NUL terminated string at a physical address.
+unsafe fn c_str(address: u64) -> &'static str {
+    let c_str = unsafe { CStr::from_ptr((PHYSICAL_MEMORY_OFFSET + address) as *const _) };
+    c_str.to_str().unwrap_or("")
+}
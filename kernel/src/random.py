@@ -0,0 +1,81 @@
+# The `random` module, see `install_random` in main.rs.
+#
+# Everything is built on `os.urandom`, like CPython's `random.SystemRandom`. That means there is
+# no state to seed, save or restore.
+
+from os import urandom as _urandom
+
+_BPF = 53  # Bits in a float's mantissa
+
+
+def getrandbits(k):
+    """Returns an int with k random bits."""
+    if k < 0:
+        raise ValueError('number of bits must be non-negative')
+    value = int.from_bytes(_urandom((k + 7) // 8), 'little')
+    return value >> ((k + 7) // 8 * 8 - k)
+
+
+def randbytes(n):
+    """Returns n random bytes."""
+    return _urandom(n)
+
+
+def random():
+    """Returns a float in [0.0, 1.0)."""
+    return getrandbits(_BPF) / (1 << _BPF)
+
+
+def seed(a=None, version=2):
+    """Does nothing, there is no state to seed."""
+
+
+def _randbelow(n):
+    k = n.bit_length()
+    value = getrandbits(k)
+    while value >= n:
+        value = getrandbits(k)
+    return value
+
+
+def randrange(start, stop=None, step=1):
+    """Returns a random element of range(start, stop, step)."""
+    values = range(start) if stop is None else range(start, stop, step)
+    if not values:
+        raise ValueError(f'empty range in randrange({start}, {stop}, {step})')
+    return values[_randbelow(len(values))]
+
+
+def randint(a, b):
+    """Returns a random int in [a, b], including b."""
+    return randrange(a, b + 1)
+
+
+def choice(seq):
+    """Returns a random element of the non-empty sequence seq."""
+    if not seq:
+        raise IndexError('Cannot choose from an empty sequence')
+    return seq[_randbelow(len(seq))]
+
+
+def shuffle(x):
+    """Shuffles the list x in place."""
+    for i in reversed(range(1, len(x))):
+        j = _randbelow(i + 1)
+        x[i], x[j] = x[j], x[i]
+
+
+def sample(population, k):
+    """Returns k distinct elements of population."""
+    pool = list(population)
+    if not 0 <= k <= len(pool):
+        raise ValueError('Sample larger than population or is negative')
+    for i in range(k):
+        j = i + _randbelow(len(pool) - i)
+        pool[i], pool[j] = pool[j], pool[i]
+    return pool[:k]
+
+
+def uniform(a, b):
+    """Returns a random float between a and b."""
+    return a + (b - a) * random()
@@ -0,0 +1,200 @@
+//! Random numbers for `os.urandom` and the `random` module.
+//!
+//! They come straight from RDRAND when the CPU has it. Otherwise, or once RDRAND keeps
+//! failing, a ChaCha20 keystream is used, keyed with whatever entropy we can find: RDSEED if
+//! only that is available, and the jitter of timing port I/O with the TSC, which is poor but
+//! better than nothing.
+
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdseed64_step, _rdtsc};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::instructions::random::RdRand;
+
+use crate::{rtc, timer};
+
+/// RDRAND can fail when it is drained, Intel recommends giving up after 10 tries.
+const RETRIES: usize = 10;
+
+enum Generator {
+    Hardware(RdRand),
+    Software(ChaCha20),
+}
+
+impl Generator {
+    fn new() -> Self {
+        match RdRand::new() {
+            Some(rdrand) => Self::Hardware(rdrand),
+            None => Self::software(),
+        }
+    }
+
+    fn software() -> Self {
+        self_check();
+        Self::Software(ChaCha20::new(gather_entropy()))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if let Self::Hardware(rdrand) = self {
+            match (0..RETRIES).find_map(|_| rdrand.get_u64()) {
+                Some(value) => return value,
+                // It is drained for good or broken, don't rely on it any longer
+                None => *self = Self::software(),
+            }
+        }
+        match self {
+            Self::Software(chacha) => chacha.next_u64(),
+            Self::Hardware(_) => unreachable!(),
+        }
+    }
+}
+
+static GENERATOR: Mutex<Option<Generator>> = Mutex::new(None);
+
+/// Fills `buffer` with random bytes.
+pub fn fill(buffer: &mut [u8]) {
+    let mut generator = GENERATOR.lock();
+    let generator = generator.get_or_insert_with(Generator::new);
+    for chunk in buffer.chunks_mut(8) {
+        let value = generator.next_u64();
+        chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+    }
+}
+
+fn has_rdseed() -> bool {
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    max_leaf >= 7 && unsafe { __cpuid_count(7, 0) }.ebx & (1 << 18) != 0
+}
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed() -> Option<u64> {
+    let mut value = 0;
+    (0..RETRIES)
+        .any(|_| unsafe { _rdseed64_step(&mut value) } == 1)
+        .then_some(value)
+}
+
+/// A key for the software generator.
+fn gather_entropy() -> [u32; 8] {
+    const SAMPLES: usize = 1024;
+
+    let mut key = [0u32; 8];
+    let mut mix = |index: usize, value: u64| {
+        key[index % 8] = key[index % 8].rotate_left(7) ^ value as u32 ^ (value >> 32) as u32;
+    };
+
+    if has_rdseed() {
+        for index in 0..8 {
+            if let Some(seed) = unsafe { rdseed() } {
+                mix(index, seed);
+            }
+        }
+    }
+    mix(0, rtc::unix_time_ns() as u64);
+    mix(1, timer::uptime_ns());
+
+    // How long port I/O takes varies a little, by how much is hard to predict
+    let mut port = Port::<u8>::new(0x80);
+    let mut last = unsafe { _rdtsc() };
+    for index in 0..SAMPLES {
+        unsafe { port.write(0) };
+        let now = unsafe { _rdtsc() };
+        mix(index, now.wrapping_sub(last));
+        last = now;
+    }
+    key
+}
+
+/// The ChaCha20 stream cipher, with a zero nonce, used as a random number generator.
+struct ChaCha20 {
+    key: [u32; 8],
+    counter: u64,
+    block: [u32; 16],
+    /// Index of the next unused word of `block`.
+    position: usize,
+}
+
+impl ChaCha20 {
+    fn new(key: [u32; 8]) -> Self {
+        Self {
+            key,
+            counter: 0,
+            block: [0; 16],
+            position: 16,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if self.position >= 16 {
+            self.refill();
+        }
+        let low = self.block[self.position] as u64;
+        let high = self.block[self.position + 1] as u64;
+        self.position += 2;
+        low | (high << 32)
+    }
+
+    fn refill(&mut self) {
+        let mut input = [0u32; 16];
+        input[..4].copy_from_slice(&CONSTANTS);
+        input[4..12].copy_from_slice(&self.key);
+        input[12] = self.counter as u32;
+        input[13] = (self.counter >> 32) as u32;
+
+        self.block = block(&input);
+        self.counter += 1;
+        self.position = 0;
+    }
+}
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+/// The ChaCha20 block function.
+fn block(input: &[u32; 16]) -> [u32; 16] {
+    let mut state = *input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, input) in state.iter_mut().zip(input) {
+        *word = word.wrapping_add(*input);
+    }
+    state
+}
+
+/// Checks `block` against the test vector of RFC 8439, section 2.3.2. A miscompiled cipher
+/// would still look random, so this is the only way to notice.
+fn self_check() {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CONSTANTS);
+    // Key 00 01 02 .. 1f, block count 1, nonce 00:00:00:09:00:00:00:4a:00:00:00:00
+    for (index, word) in input[4..12].iter_mut().enumerate() {
+        let first = index as u32 * 4;
+        *word = u32::from_le_bytes([0, 1, 2, 3].map(|offset| (first + offset) as u8));
+    }
+    input[12..].copy_from_slice(&[0x00000001, 0x09000000, 0x4a000000, 0x00000000]);
+
+    let expected = [
+        0xe4e7f110, 0x15593bd1, 0x1fdd0f50, 0xc47120a3, 0xc7f4d1c7, 0x0368c033, 0x9aaa2204,
+        0x4e6cd4c3, 0x466482d2, 0x09aa9f07, 0x05d7c214, 0xa2028bd9, 0xd19c12b5, 0xb94e16de,
+        0xe883d0cb, 0x4e3c50a2,
+    ];
+    assert_eq!(block(&input), expected, "ChaCha20 self-check failed");
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
@@ -0,0 +1,240 @@
+//! Returning to the REPL after an unrecoverable error.
+//!
+//! `catch` records the callee-saved registers and stack pointer before running a closure, and
+//! `recover` jumps straight back there, abandoning everything on the stack in between. No
+//! destructors run, so whatever the abandoned frames owned is leaked, and the locks and borrows
+//! they held are never released. Nothing those frames could reach may be used afterwards, which
+//! is why the REPL replaces the whole interpreter. This is only meant as a last resort which
+//! beats rebooting, e.g. when a single allocation can never be satisfied.
+//!
+//! `catch_fault` works the same way, except that the exception handler jumps back when the
+//! closure causes a page fault or similar. This lets Python poke at arbitrary addresses.
+
+use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use spin::Mutex;
+use x86_64::registers::control::Cr2;
+
+use crate::interrupts::ExceptionFrame;
+
+/// Register state saved by `recovery_call`. Layout is shared with the assembly below.
+#[repr(C)]
+#[derive(Default)]
+struct JumpBuffer {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+    rip: u64,
+    mxcsr: u32,
+    fpu_control: u32,
+}
+
+core::arch::global_asm!(
+    r#"
+.global recovery_call
+recovery_call:
+    mov [rdi + 0x00], rbx
+    mov [rdi + 0x08], rbp
+    mov [rdi + 0x10], r12
+    mov [rdi + 0x18], r13
+    mov [rdi + 0x20], r14
+    mov [rdi + 0x28], r15
+    lea rax, [rsp + 8]
+    mov [rdi + 0x30], rax
+    mov rax, [rsp]
+    mov [rdi + 0x38], rax
+    stmxcsr [rdi + 0x40]
+    fnstcw [rdi + 0x44]
+    sub rsp, 8
+    mov rdi, rdx
+    call rsi
+    add rsp, 8
+    xor eax, eax
+    ret
+
+.global recovery_jump
+recovery_jump:
+    mov rbx, [rdi + 0x00]
+    mov rbp, [rdi + 0x08]
+    mov r12, [rdi + 0x10]
+    mov r13, [rdi + 0x18]
+    mov r14, [rdi + 0x20]
+    mov r15, [rdi + 0x28]
+    ldmxcsr [rdi + 0x40]
+    fldcw [rdi + 0x44]
+    mov rsp, [rdi + 0x30]
+    mov eax, 1
+    jmp [rdi + 0x38]
+"#
+);
+
+unsafe extern "C" {
+    /// Saves the registers to `buffer` and calls `f(data)`. Returns 0 when `f` returns, or 1
+    /// when `recovery_jump` was called with `buffer`.
+    fn recovery_call(buffer: *mut JumpBuffer, f: extern "C" fn(*mut u8), data: *mut u8) -> u64;
+
+    fn recovery_jump(buffer: *const JumpBuffer) -> !;
+}
+
+/// The innermost active `catch`, if any.
+static RECOVERY_POINT: AtomicPtr<JumpBuffer> = AtomicPtr::new(ptr::null_mut());
+
+/// `recover` was called while `catch` was running its closure.
+#[derive(Debug)]
+pub struct Recovered;
+
+/// Runs `f`, returning `Err(Recovered)` if `recover` is called before it finishes.
+pub fn catch<F: FnOnce()>(f: F) -> Result<(), Recovered> {
+    match call_recoverable(&RECOVERY_POINT, f) {
+        false => Ok(()),
+        true => Err(Recovered),
+    }
+}
+
+/// Jumps back to the innermost `catch`. Returns if there is none.
+pub fn recover() {
+    let buffer = RECOVERY_POINT.load(Ordering::SeqCst);
+    if !buffer.is_null() {
+        unsafe { recovery_jump(buffer) }
+    }
+}
+
+/// Runs `f` with a fresh jump buffer installed as the innermost recovery point in `point`.
+/// Returns whether something jumped back to it.
+fn call_recoverable<F: FnOnce()>(point: &AtomicPtr<JumpBuffer>, f: F) -> bool {
+    extern "C" fn trampoline<F: FnOnce()>(data: *mut u8) {
+        let f = unsafe { &mut *(data as *mut Option<F>) };
+        (f.take().unwrap())();
+    }
+
+    let mut f = Some(f);
+    let mut buffer = JumpBuffer::default();
+    let previous = point.swap(&mut buffer, Ordering::SeqCst);
+    let jumped = unsafe {
+        recovery_call(
+            &mut buffer,
+            trampoline::<F>,
+            &mut f as *mut Option<F> as *mut u8,
+        )
+    };
+    point.store(previous, Ordering::SeqCst);
+    jumped != 0
+}
+
+/// The innermost active `catch_fault`, if any.
+static FAULT_POINT: AtomicPtr<JumpBuffer> = AtomicPtr::new(ptr::null_mut());
+/// The fault that made `redirect_fault` jump back to `catch_fault`.
+static LAST_FAULT: Mutex<Option<Fault>> = Mutex::new(None);
+
+/// Exceptions `catch_fault` catches: stack segment faults, general protection faults, page
+/// faults and alignment checks.
+const CATCHABLE_VECTORS: [u64; 4] = [12, 13, 14, 17];
+
+/// A CPU exception caught by `catch_fault`.
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub vector: u64,
+    pub error_code: u64,
+    /// The address that was accessed, for page faults.
+    pub address: Option<u64>,
+}
+
+impl Fault {
+    pub fn description(&self) -> &'static str {
+        match self.vector {
+            12 => "stack segment fault",
+            13 => "general protection fault",
+            14 => "page fault",
+            _ => "alignment check",
+        }
+    }
+}
+
+/// Runs `f`, returning the fault instead if it causes one of the exceptions in
+/// `CATCHABLE_VECTORS`.
+///
+/// `f` is abandoned at the faulting instruction, so it should do nothing but the access that
+/// may fault: it must not take locks, allocate, or own anything that needs dropping.
+pub fn catch_fault<R, F: FnOnce() -> R>(f: F) -> Result<R, Fault> {
+    let mut result = None;
+    match call_recoverable(&FAULT_POINT, || result = Some(f())) {
+        false => Ok(result.unwrap()),
+        true => Err(LAST_FAULT.lock().take().unwrap()),
+    }
+}
+
+/// Values that can be read from and written to arbitrary addresses with a single `mov`.
+///
+/// Unlike `read_volatile` and `write_volatile`, these have no preconditions on the address,
+/// which debug builds would check and panic on. Whatever is wrong with the address is left for
+/// the CPU to fault on, so they are meant to run inside `catch_fault`.
+pub trait RawAccess: Copy {
+    unsafe fn read(address: u64) -> Self;
+    unsafe fn write(address: u64, value: Self);
+}
+
+macro_rules! raw_access {
+    ($($ty:ty => $class:ident $value:literal $size:literal),* $(,)?) => {
+        $(
+            impl RawAccess for $ty {
+                unsafe fn read(address: u64) -> Self {
+                    let value: $ty;
+                    unsafe {
+                        asm!(
+                            concat!("mov ", $value, ", ", $size, " ptr [{address}]"),
+                            address = in(reg) address,
+                            value = out($class) value,
+                            options(nostack, preserves_flags),
+                        )
+                    };
+                    value
+                }
+
+                unsafe fn write(address: u64, value: Self) {
+                    unsafe {
+                        asm!(
+                            concat!("mov ", $size, " ptr [{address}], ", $value),
+                            address = in(reg) address,
+                            value = in($class) value,
+                            options(nostack, preserves_flags),
+                        )
+                    };
+                }
+            }
+        )*
+    };
+}
+
+raw_access! {
+    u8 => reg_byte "{value}" "byte",
+    i8 => reg_byte "{value}" "byte",
+    u16 => reg "{value:x}" "word",
+    i16 => reg "{value:x}" "word",
+    u32 => reg "{value:e}" "dword",
+    i32 => reg "{value:e}" "dword",
+    u64 => reg "{value:r}" "qword",
+    i64 => reg "{value:r}" "qword",
+}
+
+/// Called by the exception handler. If a `catch_fault` is running and the exception is one it
+/// catches, changes `frame` to return into `recovery_jump` instead of the faulting instruction.
+pub fn redirect_fault(frame: &mut ExceptionFrame) -> bool {
+    let buffer = FAULT_POINT.load(Ordering::SeqCst);
+    if buffer.is_null() || !CATCHABLE_VECTORS.contains(&frame.vector) {
+        return false;
+    }
+
+    *LAST_FAULT.lock() = Some(Fault {
+        vector: frame.vector,
+        error_code: frame.error_code,
+        address: (frame.vector == 14).then(|| Cr2::read().as_u64()),
+    });
+    frame.rip = recovery_jump as usize as u64;
+    frame.rdi = buffer as u64;
+    true
+}
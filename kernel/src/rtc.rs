@@ -0,0 +1,192 @@
+//! The CMOS real-time clock, which is where the date comes from.
+//!
+//! The clock is only read once during boot, the wall-clock time is then kept by the system
+//! timer, so it advances in step with `time.monotonic()`. The RTC has no notion of time zones,
+//! so we assume it is set to UTC.
+
+use core::sync::atomic::{AtomicI64, Ordering};
+use x86_64::instructions::port::Port;
+
+use crate::timer;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Set in the address port to keep NMIs disabled while we talk to the CMOS.
+const NMI_DISABLE: u8 = 0x80;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+/// Read-only, so selecting it is harmless. Used to leave the address port with NMIs enabled.
+const STATUS_D: u8 = 0x0D;
+
+/// Status A: the clock is updating and its registers shouldn't be read.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status B: hours count from 0 to 23 rather than 1 to 12.
+const HOURS_24: u8 = 1 << 1;
+/// Status B: the registers are binary rather than BCD.
+const BINARY: u8 = 1 << 2;
+/// Set in the hours register for PM times in 12 hour mode.
+const PM: u8 = 0x80;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// Unix time in nanoseconds at which the timer was started, i.e. boot.
+static BOOT_TIME_NS: AtomicI64 = AtomicI64::new(0);
+
+/// A broken down UTC time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn unix_time(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+
+    pub fn from_unix_time(time: i64) -> Self {
+        let days = time.div_euclid(SECONDS_PER_DAY);
+        let seconds = time.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// Day of the week, Monday is 0.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        (days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) as u8
+    }
+
+    /// Day of the year, January 1st is 1.
+    pub fn yearday(&self) -> u16 {
+        (days_from_civil(self.year, self.month, self.day) - days_from_civil(self.year, 1, 1) + 1)
+            as u16
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    // Count years from March, so the leap day is at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8;
+    let month = (month_from_march + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month as u8, day)
+}
+
+fn read_register(register: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(NMI_DISABLE | register);
+        let value = Port::<u8>::new(CMOS_DATA).read();
+        Port::<u8>::new(CMOS_ADDRESS).write(STATUS_D);
+        value
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0
+}
+
+/// The raw time registers, in whatever format the clock uses.
+fn read_registers() -> [u8; 6] {
+    while update_in_progress() {
+        core::hint::spin_loop();
+    }
+    [SECONDS, MINUTES, HOURS, DAY, MONTH, YEAR].map(read_register)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Reads the current time from the RTC.
+pub fn read() -> DateTime {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // An update can still start while we read, so read until we get the same thing twice
+        let mut registers = read_registers();
+        loop {
+            let again = read_registers();
+            if again == registers {
+                break;
+            }
+            registers = again;
+        }
+        let [second, minute, hour, day, month, year] = registers;
+        let status = read_register(STATUS_B);
+
+        let pm = hour & PM != 0;
+        let decode = |value: u8| match status & BINARY {
+            0 => from_bcd(value),
+            _ => value,
+        };
+        let mut hour = decode(hour & !PM);
+        if status & HOURS_24 == 0 {
+            // 12 AM is midnight, 12 PM is noon
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+
+        DateTime {
+            // The century register isn't in the same place everywhere, assume this century
+            year: 2000 + decode(year) as i64,
+            month: decode(month),
+            day: decode(day),
+            hour,
+            minute: decode(minute),
+            second: decode(second),
+        }
+    })
+}
+
+/// Reads the RTC and starts keeping the wall-clock time. Must be called after `timer::init`.
+pub fn init() {
+    let now = read().unix_time() * NANOS_PER_SECOND;
+    BOOT_TIME_NS.store(now - timer::uptime_ns() as i64, Ordering::Relaxed);
+}
+
+/// Nanoseconds since the Unix epoch.
+pub fn unix_time_ns() -> i64 {
+    BOOT_TIME_NS.load(Ordering::Relaxed) + timer::uptime_ns() as i64
+}
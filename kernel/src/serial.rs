@@ -0,0 +1,71 @@
+//! The first 16550 serial port (COM1), used as a second console for diagnostics.
+
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const COM1: u16 = 0x3F8;
+
+/// Line status bit: the transmitter can take another byte.
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+pub static SERIAL: Mutex<Option<SerialPort>> = Mutex::new(None);
+
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    /// Sets the port up for 115200 baud 8N1. Returns `None` if the port doesn't work, which
+    /// is checked by sending a byte in loopback mode.
+    fn new(base: u16) -> Option<Self> {
+        let port = Self { base };
+        port.write_register(1, 0x00); // Disable interrupts
+        port.write_register(3, 0x80); // Enable the divisor latch
+        port.write_register(0, 0x01); // Divisor 1, i.e. 115200 baud
+        port.write_register(1, 0x00);
+        port.write_register(3, 0x03); // 8 bits, no parity, one stop bit
+        port.write_register(2, 0xC7); // Enable and clear the FIFOs
+        port.write_register(4, 0x1E); // Loopback mode
+
+        port.write_register(0, 0xAE);
+        if port.read_register(0) != 0xAE {
+            return None;
+        }
+
+        port.write_register(4, 0x0F); // Normal operation
+        Some(port)
+    }
+
+    fn write_register(&self, offset: u16, value: u8) {
+        unsafe { Port::new(self.base + offset).write(value) };
+    }
+
+    fn read_register(&self, offset: u16) -> u8 {
+        unsafe { Port::new(self.base + offset).read() }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.read_register(5) & TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write_register(0, byte);
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Looks for the serial port. Output is discarded if there is none.
+pub fn init() {
+    *SERIAL.lock() = SerialPort::new(COM1);
+}
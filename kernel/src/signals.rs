@@ -0,0 +1,66 @@
+//! Delivering kernel events to the interpreter.
+//!
+//! RustPython checks for pending signals between bytecode instructions and calls the Python
+//! handler stored in `vm.signal_handlers` for each one that fired. The kernel reuses that
+//! machinery: `raise` may be called from anywhere, including the allocator, and the handler
+//! runs at the next safe point in the interpreter.
+
+use core::sync::atomic::Ordering;
+
+use rustpython_vm::signal::{TRIGGERS, set_triggered};
+use rustpython_vm::{PyObjectRef, VirtualMachine};
+
+/// Ctrl+C was pressed. Same number as on Unix.
+pub const SIGINT: usize = 2;
+/// The heap had to dip into its emergency pool.
+pub const SIGMEMORY: usize = 33;
+/// An interrupt line handled by Python code fired.
+pub const SIGIRQ: usize = 34;
+/// The alarm set with `timer::set_alarm` went off.
+pub const SIGTIMER: usize = 35;
+
+/// Marks `signum` as pending. Never allocates.
+pub fn raise(signum: usize) {
+    TRIGGERS[signum].store(true, Ordering::Relaxed);
+    set_triggered();
+}
+
+/// Whether `signum` was raised and its handler hasn't run yet.
+pub fn pending(signum: usize) -> bool {
+    TRIGGERS[signum].load(Ordering::Relaxed)
+}
+
+/// Whether a signal with a handler was raised and the handler hasn't run yet. Signals without a
+/// handler are just dropped by the interpreter, so they are no reason to wake up.
+///
+/// If so, this also makes sure the interpreter gets to them: when a handler raises, it stops
+/// looking at the signals after that one, but forgets that some are still pending. Waking up
+/// for them without that would just spin.
+pub fn any_handled_pending(vm: &VirtualMachine) -> bool {
+    let Some(handlers) = vm.signal_handlers.as_deref() else {
+        return false;
+    };
+    let handlers = handlers.borrow();
+    let pending = TRIGGERS
+        .iter()
+        .zip(handlers.iter())
+        .any(|(trigger, handler)| handler.is_some() && trigger.load(Ordering::Relaxed));
+    if pending {
+        set_triggered();
+    }
+    pending
+}
+
+/// Handles `signum` outside the interpreter: clears it, and returns whether it was pending.
+pub fn take(signum: usize) -> bool {
+    TRIGGERS[signum].swap(false, Ordering::Relaxed)
+}
+
+/// Installs `handler` to be called as `handler(signum, frame)` when `signum` is raised.
+pub fn set_handler(vm: &VirtualMachine, signum: usize, handler: PyObjectRef) {
+    let handlers = vm
+        .signal_handlers
+        .as_deref()
+        .expect("The VM was created without signal handlers");
+    handlers.borrow_mut()[signum] = Some(handler);
+}
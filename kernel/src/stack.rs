@@ -0,0 +1,143 @@
+//! Kernel stacks and their guard pages.
+//!
+//! The bootloader places the boot stack at `KERNEL_STACK_ADDRESS` and maps `KERNEL_STACK_PAGES`
+//! pages directly above the first page, which is left as the guard page. When booted through
+//! Multiboot, the boot stack is a static buffer instead.
+//! Further stacks are allocated with `Stack::allocate`, each with an unmapped guard page below
+//! it. Overflowing any of them therefore page faults instead of silently overwriting memory.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::{MapToError, UnmapError};
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+
+use crate::boot::BootStack;
+use crate::memory;
+
+/// The address at which the bootloader places the kernel stack, see `BOOTLOADER_CONFIG`.
+pub const KERNEL_STACK_ADDRESS: u64 = 0xFFFFFF8000000000;
+/// Size of the kernel stack in 4 KiB pages.
+pub const KERNEL_STACK_PAGES: u64 = 128;
+
+/// Start of the virtual window that `Stack::allocate` carves stacks out of.
+const STACK_WINDOW_START: u64 = 0x_6666_0000_0000;
+const MAX_STACKS: usize = 8;
+const PAGE_SIZE: u64 = 4096;
+
+/// Usable ranges of all known stacks. The page below each `bottom` is its guard page.
+/// Atomics rather than a lock, since the double fault handler reads these.
+static BOTTOMS: [AtomicU64; MAX_STACKS] = [const { AtomicU64::new(0) }; MAX_STACKS];
+static TOPS: [AtomicU64; MAX_STACKS] = [const { AtomicU64::new(0) }; MAX_STACKS];
+static STACK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACK_WINDOW_START);
+
+/// A stack with an unmapped guard page below it.
+pub struct Stack {
+    bottom: u64,
+    top: u64,
+}
+
+impl Stack {
+    /// Maps a new stack of at least `size` bytes.
+    pub fn allocate(size: u64) -> Result<Self, MapToError<Size4KiB>> {
+        let size = size.next_multiple_of(PAGE_SIZE);
+        let guard = NEXT_STACK.fetch_add(size + PAGE_SIZE, Ordering::SeqCst);
+        let bottom = guard + PAGE_SIZE;
+        let top = bottom + size;
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let first = Page::containing_address(VirtAddr::new(bottom));
+        let last = Page::containing_address(VirtAddr::new(top - 1));
+        for page in Page::range_inclusive(first, last) {
+            memory::map_page(page, flags)?;
+        }
+
+        register(bottom, top);
+        Ok(Self { bottom, top })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.top - self.bottom
+    }
+
+    /// Switches to this stack and runs `f` on it. The current stack is never returned to.
+    pub fn switch_to<F: FnOnce() -> !>(self, f: F) -> ! {
+        extern "C" fn entry<F: FnOnce() -> !>(f: *mut F) -> ! {
+            // The closure still lives on the old stack, which is never touched again
+            let f = unsafe { f.read() };
+            f()
+        }
+
+        let mut f = core::mem::ManuallyDrop::new(f);
+        unsafe {
+            asm!(
+                "mov rsp, {top}",
+                "call {entry}",
+                top = in(reg) self.top,
+                entry = in(reg) entry::<F> as usize,
+                in("rdi") &mut *f as *mut F,
+                options(noreturn),
+            )
+        }
+    }
+}
+
+fn register(bottom: u64, top: u64) {
+    let index = STACK_COUNT.fetch_add(1, Ordering::SeqCst);
+    assert!(index < MAX_STACKS, "Too many kernel stacks");
+    BOTTOMS[index].store(bottom, Ordering::SeqCst);
+    TOPS[index].store(top, Ordering::SeqCst);
+}
+
+fn stacks() -> impl Iterator<Item = (u64, u64)> {
+    (0..STACK_COUNT.load(Ordering::SeqCst).min(MAX_STACKS)).map(|i| {
+        (
+            BOTTOMS[i].load(Ordering::SeqCst),
+            TOPS[i].load(Ordering::SeqCst),
+        )
+    })
+}
+
+/// Registers the boot stack. For the bootloader's stack, this also makes sure nothing is
+/// mapped in the guard page below it. The Multiboot stack lives in the kernel image and has no
+/// guard page.
+pub fn init(stack: &BootStack) {
+    match *stack {
+        BootStack::Bootloader => {
+            let bottom = KERNEL_STACK_ADDRESS + PAGE_SIZE;
+            let guard = Page::containing_address(VirtAddr::new(KERNEL_STACK_ADDRESS));
+            match memory::unmap_page(guard) {
+                Ok(_) | Err(UnmapError::PageNotMapped) => (),
+                Err(e) => panic!("Failed to unmap the stack guard page: {e:?}"),
+            }
+            register(bottom, bottom + KERNEL_STACK_PAGES * PAGE_SIZE);
+        }
+        BootStack::Static { bottom, top } => register(bottom, top),
+    }
+}
+
+/// Whether `address` lies in a stack guard page.
+pub fn is_guard_page(address: VirtAddr) -> bool {
+    let address = address.as_u64();
+    stacks().any(|(bottom, _)| (bottom - PAGE_SIZE..bottom).contains(&address))
+}
+
+/// Lowest usable address of the stack we are currently running on, `None` if it was never
+/// registered.
+pub fn bottom() -> Option<u64> {
+    let rsp = stack_pointer();
+    stacks()
+        .find(|&(bottom, top)| (bottom..=top).contains(&rsp))
+        .map(|(bottom, _)| bottom)
+}
+
+/// The current value of the stack pointer.
+#[inline(always)]
+pub fn stack_pointer() -> u64 {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp) };
+    rsp
+}
@@ -0,0 +1,116 @@
+//! The system timer.
+//!
+//! The PIT raises IRQ0 `TICK_HZ` times per second, which wakes up idle loops and counts
+//! ticks. Timestamps come from the TSC, whose frequency is measured against the PIT during
+//! `init`. If the TSC doesn't appear to be ticking at all, timestamps fall back to counting
+//! ticks.
+
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+use crate::interrupts::{self, InterruptIndex};
+use crate::signals;
+
+/// Each tick wakes the CPU from idling, so this is kept low. Timestamps don't depend on it.
+pub const TICK_HZ: u64 = 100;
+
+/// The frequency of the PIT's oscillator.
+const PIT_HZ: u64 = 1_193_182;
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+/// How many ticks the TSC is measured for.
+const CALIBRATION_TICKS: u64 = 10;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// TSC frequency in Hz, or 0 if it couldn't be measured.
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+/// TSC value at the end of `init`, which is where the clock starts.
+static TSC_START: AtomicU64 = AtomicU64::new(0);
+/// Tick at which `SIGTIMER` is raised, or `u64::MAX` for never.
+static ALARM: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Starts the periodic tick and calibrates the TSC. Enables interrupts.
+pub fn init() {
+    let divisor = (PIT_HZ / TICK_HZ) as u16;
+    unsafe {
+        // Channel 0, low byte then high byte, mode 2 (rate generator)
+        Port::<u8>::new(PIT_COMMAND).write(0b00_11_010_0);
+        let mut data = Port::<u8>::new(PIT_CHANNEL_0);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+    interrupts::enable_irq(InterruptIndex::Timer);
+
+    // Start measuring right after a tick
+    let start = ticks() + 1;
+    interrupts::idle_until(|| ticks() >= start);
+    let tsc_start = rdtsc();
+    interrupts::idle_until(|| ticks() >= start + CALIBRATION_TICKS);
+    let tsc_end = rdtsc();
+
+    let tsc_hz = (tsc_end - tsc_start) * TICK_HZ / CALIBRATION_TICKS;
+    TSC_HZ.store(tsc_hz, Ordering::SeqCst);
+    TSC_START.store(tsc_end, Ordering::SeqCst);
+}
+
+/// Called by the IRQ0 handler.
+pub fn handle_interrupt() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if ticks >= ALARM.load(Ordering::Relaxed) {
+        ALARM.store(u64::MAX, Ordering::Relaxed);
+        signals::raise(signals::SIGTIMER);
+    }
+}
+
+/// Raises `signals::SIGTIMER` once `ticks()` reaches `tick`, replacing the previous alarm.
+pub fn set_alarm(tick: Option<u64>) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let tick = tick.unwrap_or(u64::MAX);
+        if ticks() >= tick {
+            ALARM.store(u64::MAX, Ordering::Relaxed);
+            signals::raise(signals::SIGTIMER);
+        } else {
+            ALARM.store(tick, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Number of ticks since the timer was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// The measured TSC frequency in Hz, if any.
+pub fn tsc_hz() -> Option<u64> {
+    Some(TSC_HZ.load(Ordering::Relaxed)).filter(|&hz| hz != 0)
+}
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Nanoseconds since `init`. Never goes backwards.
+pub fn uptime_ns() -> u64 {
+    match tsc_hz() {
+        Some(hz) => {
+            let elapsed = rdtsc().saturating_sub(TSC_START.load(Ordering::Relaxed));
+            (elapsed as u128 * NANOS_PER_SECOND as u128 / hz as u128) as u64
+        }
+        None => ticks() * (NANOS_PER_SECOND / TICK_HZ),
+    }
+}
+
+/// Halts until `uptime_ns()` reaches `deadline`, or until `wake` returns true. Returns
+/// whether the deadline was reached.
+pub fn sleep_until(deadline: u64, mut wake: impl FnMut() -> bool) -> bool {
+    // Halting only ends at the next tick, so spin through the last one rather than overshoot
+    let tick_ns = NANOS_PER_SECOND / TICK_HZ;
+    interrupts::idle_until(|| uptime_ns().saturating_add(tick_ns) >= deadline || wake());
+    while uptime_ns() < deadline && !wake() {
+        core::hint::spin_loop();
+    }
+    uptime_ns() >= deadline
+}
@@ -0,0 +1,367 @@
+use bootloader_api::info::FrameBuffer;
+use core::fmt;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use volatile::Volatile;
+
+use crate::framebuffer::FramebufferConsole;
+
+lazy_static! {
+    /// A global `Writer` instance that can be used for printing to the screen.
+    ///
+    /// Used by the `print!` and `println!` macros. Output is discarded until one of the `init_*`
+    /// functions picked a display.
+    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+        column_position: 0,
+        foreground: Color::Yellow,
+        background: Color::Black,
+        display: Display::None,
+        cursor: None,
+    });
+}
+
+/// Sends all further output to the VGA text buffer.
+pub fn init_text_mode() {
+    let buffer = (crate::PHYSICAL_MEMORY_OFFSET + 0xb8000) as *mut Buffer;
+    let mut writer = WRITER.lock();
+    writer.display = Display::Text(unsafe { &mut *buffer });
+    for row in 0..BUFFER_HEIGHT {
+        writer.clear_row(row);
+    }
+}
+
+/// Sends all further output to the framebuffer.
+pub fn init_framebuffer(framebuffer: &'static mut FrameBuffer) {
+    WRITER.lock().display = Display::Framebuffer(FramebufferConsole::new(framebuffer));
+}
+
+/// The standard color palette in VGA text mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+impl Color {
+    const NAMES: [(&'static str, Color); 16] = [
+        ("black", Color::Black),
+        ("blue", Color::Blue),
+        ("green", Color::Green),
+        ("cyan", Color::Cyan),
+        ("red", Color::Red),
+        ("magenta", Color::Magenta),
+        ("brown", Color::Brown),
+        ("light_gray", Color::LightGray),
+        ("dark_gray", Color::DarkGray),
+        ("light_blue", Color::LightBlue),
+        ("light_green", Color::LightGreen),
+        ("light_cyan", Color::LightCyan),
+        ("light_red", Color::LightRed),
+        ("pink", Color::Pink),
+        ("yellow", Color::Yellow),
+        ("white", Color::White),
+    ];
+
+    /// Looks up a color by its snake case name, e.g. `light_green`.
+    pub fn from_name(name: &str) -> Option<Color> {
+        Self::NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, color)| color)
+    }
+
+    /// The color in the standard VGA palette.
+    pub fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Black => (0x00, 0x00, 0x00),
+            Color::Blue => (0x00, 0x00, 0xaa),
+            Color::Green => (0x00, 0xaa, 0x00),
+            Color::Cyan => (0x00, 0xaa, 0xaa),
+            Color::Red => (0xaa, 0x00, 0x00),
+            Color::Magenta => (0xaa, 0x00, 0xaa),
+            Color::Brown => (0xaa, 0x55, 0x00),
+            Color::LightGray => (0xaa, 0xaa, 0xaa),
+            Color::DarkGray => (0x55, 0x55, 0x55),
+            Color::LightBlue => (0x55, 0x55, 0xff),
+            Color::LightGreen => (0x55, 0xff, 0x55),
+            Color::LightCyan => (0x55, 0xff, 0xff),
+            Color::LightRed => (0xff, 0x55, 0x55),
+            Color::Pink => (0xff, 0x55, 0xff),
+            Color::Yellow => (0xff, 0xff, 0x55),
+            Color::White => (0xff, 0xff, 0xff),
+        }
+    }
+}
+
+/// A combination of a foreground and a background color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+struct ColorCode(u8);
+
+impl ColorCode {
+    /// Create a new `ColorCode` with the given foreground and background colors.
+    fn new(foreground: Color, background: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (foreground as u8))
+    }
+}
+
+/// A screen character in the VGA text buffer, consisting of an ASCII character and a `ColorCode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct ScreenChar {
+    ascii_character: u8,
+    color_code: ColorCode,
+}
+
+/// The height of the text buffer (normally 25 lines).
+const BUFFER_HEIGHT: usize = 25;
+/// The width of the text buffer (normally 80 columns).
+const BUFFER_WIDTH: usize = 80;
+
+/// A structure representing the VGA text buffer.
+#[repr(transparent)]
+struct Buffer {
+    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+/// Where the `Writer` puts its characters.
+enum Display {
+    None,
+    /// The VGA text buffer.
+    Text(&'static mut Buffer),
+    Framebuffer(FramebufferConsole),
+}
+
+/// A writer type that allows writing ASCII bytes and strings to the screen.
+///
+/// Wraps lines at the width of the display. Supports newline characters and implements the
+/// `core::fmt::Write` trait.
+pub struct Writer {
+    pub column_position: usize,
+    foreground: Color,
+    background: Color,
+    display: Display,
+    /// Where the framebuffer cursor was last drawn.
+    cursor: Option<(usize, usize)>,
+}
+
+impl Writer {
+    /// Width of the display in characters.
+    fn columns(&self) -> usize {
+        match &self.display {
+            Display::Framebuffer(console) => console.columns(),
+            _ => BUFFER_WIDTH,
+        }
+    }
+
+    /// Height of the display in characters.
+    fn rows(&self) -> usize {
+        match &self.display {
+            Display::Framebuffer(console) => console.rows(),
+            _ => BUFFER_HEIGHT,
+        }
+    }
+
+    /// Writes an ASCII byte to the buffer.
+    ///
+    /// Wraps lines at the display width. Supports the `\n` newline character.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.hide_cursor();
+        match byte {
+            b'\n' => self.new_line(),
+            byte => {
+                if self.column_position >= self.columns() {
+                    self.new_line();
+                }
+
+                let row = self.rows() - 1;
+                let col = self.column_position;
+
+                let (foreground, background) = (self.foreground, self.background);
+                match &mut self.display {
+                    Display::None => (),
+                    Display::Text(buffer) => buffer.chars[row][col].write(ScreenChar {
+                        ascii_character: byte,
+                        color_code: ColorCode::new(foreground, background),
+                    }),
+                    Display::Framebuffer(console) => {
+                        let c = match byte {
+                            0x20..=0x7e => byte as char,
+                            _ => '\u{25a0}',
+                        };
+                        console.draw_char(col, row, c, foreground, background);
+                    }
+                }
+                self.column_position += 1;
+            }
+        }
+    }
+
+    /// Writes the given ASCII string to the buffer.
+    ///
+    /// Wraps lines at the display width. Supports the `\n` newline character. Does **not**
+    /// support strings with non-ASCII characters, since they can't be printed in the VGA text
+    /// mode.
+    fn write_string(&mut self, s: &str) {
+        for byte in s.bytes() {
+            match byte {
+                // printable ASCII byte or newline
+                0x20..=0x7e | b'\n' => self.write_byte(byte),
+                // not part of printable ASCII range
+                _ => self.write_byte(0xfe),
+            }
+        }
+    }
+
+    /// Shifts all lines one line up and clears the last row.
+    fn new_line(&mut self) {
+        match &mut self.display {
+            Display::None => (),
+            Display::Text(buffer) => {
+                for row in 1..BUFFER_HEIGHT {
+                    for col in 0..BUFFER_WIDTH {
+                        let character = buffer.chars[row][col].read();
+                        buffer.chars[row - 1][col].write(character);
+                    }
+                }
+            }
+            Display::Framebuffer(console) => console.scroll_up(),
+        }
+        self.clear_row(self.rows() - 1);
+        self.column_position = 0;
+    }
+
+    /// Clears a row by overwriting it with blank characters.
+    fn clear_row(&mut self, row: usize) {
+        let background = self.background;
+        match &mut self.display {
+            Display::None => (),
+            Display::Text(buffer) => {
+                let blank = ScreenChar {
+                    ascii_character: b' ',
+                    color_code: ColorCode::new(self.foreground, background),
+                };
+                for col in 0..BUFFER_WIDTH {
+                    buffer.chars[row][col].write(blank);
+                }
+            }
+            Display::Framebuffer(console) => console.clear_row(row, background),
+        }
+    }
+
+    /// Erases the framebuffer cursor, so it doesn't get scrolled along with the text.
+    fn hide_cursor(&mut self) {
+        if let (Some((col, row)), Display::Framebuffer(console)) =
+            (self.cursor.take(), &mut self.display)
+        {
+            console.draw_cursor(col, row, self.background);
+        }
+    }
+
+    /// Changes the given colors and clears the screen with the new background.
+    pub fn set_colors(&mut self, foreground: Option<Color>, background: Option<Color>) {
+        self.hide_cursor();
+        self.foreground = foreground.unwrap_or(self.foreground);
+        self.background = background.unwrap_or(self.background);
+        for row in 0..self.rows() {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+    }
+
+    pub fn update_cursor(&mut self) {
+        let (col, row) = (self.column_position, self.rows() - 1);
+        match &mut self.display {
+            Display::None => (),
+            Display::Text(_) => update_cursor(col as _, row as _),
+            Display::Framebuffer(console) => {
+                console.draw_cursor(col, row, self.foreground);
+                self.cursor = Some((col, row));
+            }
+        }
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+/// Like the `print!` macro in the standard library, but prints to the VGA text buffer.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+}
+
+/// Like the `println!` macro in the standard library, but prints to the VGA text buffer.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints the given formatted string to the VGA text buffer
+/// through the global `WRITER` instance.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        WRITER.lock().write_fmt(args).unwrap();
+    });
+}
+
+/// https://wiki.osdev.org/Text_Mode_Cursor
+pub fn enable_cursor()
+{
+    /*
+    let cursor_start: u8 = 0;
+    let cursor_end: u8 = (BUFFER_HEIGHT - 1) as _;
+    outb(0x3D4, 0x0A);
+    outb(0x3D5, (inb(0x3D5) & 0xC0) | cursor_start);
+
+    outb(0x3D4, 0x0B);
+    outb(0x3D5, (inb(0x3D5) & 0xE0) | cursor_end);
+    */
+}
+
+/// https://wiki.osdev.org/Text_Mode_Cursor
+fn update_cursor(x: u32, y: u32)
+{
+	let pos: u16 = (y * BUFFER_WIDTH as u32 + x).try_into().unwrap();
+
+	outb(0x3D4, 0x0F);
+	outb(0x3D5, (pos & 0xFF) as u8);
+	outb(0x3D4, 0x0E);
+	outb(0x3D5, ((pos >> 8) & 0xFF) as u8);
+}
+
+fn outb(port: u16, value: u8) {
+    unsafe {
+        x86_64::instructions::port::Port::new(port).write(value);
+    }
+}
+
+fn inb(port: u16) -> u8 {
+    unsafe {
+        x86_64::instructions::port::Port::new(port).read()
+    }
+}
@@ -1 +1 @@
-qemu-system-x86_64 -enable-kvm -cpu host -drive format=raw,file=target/x86_64-blog_os/debug/bootimage-python_os.bin
+qemu-system-x86_64 -enable-kvm -cpu host -drive format=raw,file=kernel/target/x86_64-blog_os/debug/python_os-bios.img "$@"
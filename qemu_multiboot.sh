@@ -0,0 +1,3 @@
+# Boots the kernel ELF directly through QEMU's Multiboot loader. Extra arguments are passed to
+# QEMU, e.g. `-initrd some_file` to load a boot module.
+qemu-system-x86_64 -enable-kvm -cpu host -kernel kernel/target/x86_64-blog_os/debug/python_os "$@"
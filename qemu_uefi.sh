@@ -0,0 +1,3 @@
+# OVMF firmware, e.g. from the `ovmf` package. Override with OVMF=/path/to/OVMF.fd
+OVMF=${OVMF:-/usr/share/ovmf/OVMF.fd}
+qemu-system-x86_64 -enable-kvm -cpu host -bios "$OVMF" -drive format=raw,file=kernel/target/x86_64-blog_os/debug/python_os-uefi.img "$@"
@@ -1,2 +1,3 @@
-cargo bootimage --release &&\
-    qemu-system-x86_64 --enable-kvm -drive format=raw,file=target/x86_64-blog_os/release/bootimage-python_os.bin
+(cd kernel && cargo build --release) &&\
+    cargo run --manifest-path boot/Cargo.toml -- kernel/target/x86_64-blog_os/release/python_os &&\
+    qemu-system-x86_64 --enable-kvm -drive format=raw,file=kernel/target/x86_64-blog_os/release/python_os-bios.img
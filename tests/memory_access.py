@@ -0,0 +1,27 @@
+# read_*/write_* must raise MemoryAccessError for bad addresses rather than halt the kernel.
+# ./qemu_multiboot.sh -initrd tests/memory_access.py -append init=memory_access.py
+
+def expect_access_error(function, *args):
+    try:
+        function(*args)
+    except MemoryAccessError:
+        return
+    raise AssertionError(f'{function.__name__}{args} did not raise MemoryAccessError')
+
+# Null
+expect_access_error(read_u8, 0)
+expect_access_error(read_u64, 0)
+expect_access_error(write_u32, 0, 1)
+
+# Misaligned and unmapped
+expect_access_error(read_u64, 1)
+expect_access_error(read_i32, 2)
+expect_access_error(write_u16, 3, 1)
+
+# Misaligned but mapped, inside a live object
+thing = object()
+read_u16(id(thing) + 1)
+read_u32(id(thing) + 1)
+read_u64(id(thing) + 3)
+
+print('memory_access: ok')
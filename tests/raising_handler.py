@@ -0,0 +1,78 @@
+# A signal handler that raises must not leave the signals after it stuck: their handlers still
+# run, and waiting doesn't turn into spinning.
+# ./qemu_multiboot.sh -initrd tests/raising_handler.py -append init=raising_handler.py
+#
+# The RTC's periodic interrupt (IRQ 8) and a timer both go off during a long builtin call,
+# which doesn't check for signals, so they end up pending at the same time. The IRQ's signal
+# is handled first, and its callback raises.
+import machine
+import time
+
+CMOS_ADDRESS = 0x70
+CMOS_DATA = 0x71
+NMI_DISABLE = 0x80
+STATUS_A = 0x0A
+STATUS_B = 0x0B
+STATUS_C = 0x0C
+STATUS_D = 0x0D
+PERIODIC_INTERRUPT = 0x40
+# Status A rate bits for 2 Hz, slow enough not to go off before the long call starts
+RATE_2HZ = 0x0F
+
+
+def cmos_read(register):
+    send_u8(CMOS_ADDRESS, NMI_DISABLE | register)
+    value = recv_u8(CMOS_DATA)
+    send_u8(CMOS_ADDRESS, STATUS_D)
+    return value
+
+
+def cmos_write(register, value):
+    send_u8(CMOS_ADDRESS, NMI_DISABLE | register)
+    send_u8(CMOS_DATA, value)
+    send_u8(CMOS_ADDRESS, STATUS_D)
+
+
+def set_periodic_interrupt(enabled):
+    status = cmos_read(STATUS_B) & ~PERIODIC_INTERRUPT
+    cmos_write(STATUS_B, status | (PERIODIC_INTERRUPT if enabled else 0))
+
+
+class Expected(Exception):
+    pass
+
+
+def rtc_callback(line):
+    # Reading status C acknowledges the interrupt
+    cmos_read(STATUS_C)
+    set_periodic_interrupt(False)
+    unregister_irq(8)
+    raise Expected()
+
+
+# Make the long call take about two seconds
+start = time.monotonic()
+sum(range(10 ** 6))
+count = int(10 ** 6 * 2 / max(time.monotonic() - start, 1e-6))
+
+status_a = cmos_read(STATUS_A)
+cmos_write(STATUS_A, (status_a & 0xF0) | RATE_2HZ)
+fired = []
+register_irq(8, rtc_callback)
+try:
+    machine.Timer(200, lambda timer: fired.append(timer), oneshot=True)
+    set_periodic_interrupt(True)
+    sum(range(count))
+    raise AssertionError('the IRQ callback did not run')
+except Expected:
+    pass
+finally:
+    cmos_write(STATUS_A, status_a)
+assert not fired, 'the timer callback ran before the IRQ callback'
+
+start = time.monotonic()
+time.sleep(0.1)
+assert fired, 'the timer callback did not run after the IRQ callback raised'
+assert time.monotonic() - start >= 0.1
+
+print('raising_handler: ok')